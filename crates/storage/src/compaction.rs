@@ -0,0 +1,125 @@
+use std::collections::HashMap;
+use std::path::Path;
+use crate::disk::{Disk, DiskConf};
+use crate::keyed_record::KeyedRecord;
+use crate::manifest::ManifestEntry;
+use crate::record::RECORD_HEADER_SIZE;
+use crate::DiskError;
+
+/// Outcome of compacting a run of sealed segments
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompactionReport {
+    /// Segments that were replaced
+    pub inputs: Vec<ManifestEntry>,
+    /// Segment that replaced them
+    pub output: ManifestEntry,
+    /// Records carried over, one per live key
+    pub kept: u64,
+    /// Overwritten values and tombstones that were dropped
+    pub dropped: u64,
+}
+
+/// Read the keyed records of `inputs`, oldest disk first, and write the latest
+/// value of every key that is not tombstoned into a brand new, sealed disk.
+///
+/// Tombstones are dropped, so `inputs` must be the oldest data there is: an
+/// older disk left out of the compaction could otherwise resurrect a deleted key.
+/// The output capacity is raised past `conf.capacity` if the live data needs it.
+pub async fn compact_disks<P: AsRef<Path> + Clone>(
+    inputs: &[&Disk],
+    conf: DiskConf<P>,
+) -> Result<(Disk, u64), DiskError> {
+    // Key -> (position of its latest write, latest write)
+    let mut latest: HashMap<&[u8], (usize, KeyedRecord)> = HashMap::new();
+    let mut total = 0usize;
+
+    for disk in inputs {
+        for record in disk.iter() {
+            let record = KeyedRecord::from_bytes(record?.payload)?;
+            latest.insert(record.key, (total, record));
+            total += 1;
+        }
+    }
+
+    let mut live: Vec<_> = latest
+        .into_values()
+        .filter(|(_, record)| !record.is_tombstone())
+        .collect();
+    live.sort_by_key(|(position, _)| *position);
+
+    let encoded: Vec<Vec<u8>> = live.iter().map(|(_, record)| record.to_vec()).collect();
+    let needed = conf.header_size as usize + encoded.iter().map(|e| RECORD_HEADER_SIZE + e.len()).sum::<usize>();
+
+    let disk = Disk::open(DiskConf {
+        capacity: conf.capacity.max(needed as u64),
+        max_items: conf.max_items.max(encoded.len() as u64),
+        header_size: conf.header_size,
+        durability: conf.durability,
+        disk_file_path: conf.disk_file_path,
+    }).await?;
+
+    for record in &encoded {
+        disk.append(record)?;
+    }
+
+    disk.seal().await?;
+
+    Ok((disk, (total - encoded.len()) as u64))
+}
+
+#[cfg(test)]
+mod compaction_tests {
+    use crate::compaction::compact_disks;
+    use crate::disk::{Disk, DiskConf};
+    use crate::keyed_record::KeyedRecord;
+    use crate::utils::test_utils::{disk_conf, get_file};
+
+    async fn disk(records: &[KeyedRecord<'_>]) -> Disk {
+        let disk = Disk::open(disk_conf(get_file(None, true))).await.unwrap();
+
+        for record in records {
+            disk.append(&record.to_vec()).unwrap();
+        }
+        disk.seal().await.unwrap();
+
+        disk
+    }
+
+    #[tokio::test]
+    pub async fn test_keeps_latest_value_per_key() {
+        let first = disk(&[
+            KeyedRecord::put(b"a", b"1"),
+            KeyedRecord::put(b"b", b"1"),
+            KeyedRecord::put(b"c", b"1"),
+        ]).await;
+        let second = disk(&[
+            KeyedRecord::put(b"a", b"2"),
+            KeyedRecord::tombstone(b"b"),
+            KeyedRecord::put(b"d", b"1"),
+        ]).await;
+
+        let (compacted, dropped) = compact_disks(&[&first, &second], DiskConf {
+            capacity: 64,
+            max_items: 1,
+            ..disk_conf(get_file(None, true))
+        }).await.unwrap();
+
+        assert!(compacted.is_sealed());
+        assert_eq!(dropped, 3);
+        assert_ne!(compacted.metadata().created_at(), 0);
+
+        let records: Vec<(Vec<u8>, Vec<u8>)> = compacted
+            .iter()
+            .map(|r| {
+                let record = KeyedRecord::from_bytes(r.unwrap().payload).unwrap();
+                (record.key.to_vec(), record.value.to_vec())
+            })
+            .collect();
+
+        assert_eq!(records, vec![
+            (b"c".to_vec(), b"1".to_vec()),
+            (b"a".to_vec(), b"2".to_vec()),
+            (b"d".to_vec(), b"1".to_vec()),
+        ]);
+    }
+}
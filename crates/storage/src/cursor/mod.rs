@@ -41,7 +41,7 @@ impl<'a> Cursor<'a> {
         }
     }
 
-    pub fn mmap_mut(data: &'a mut MmapMut) -> Self {
+    pub fn mmap_mut(data: &'a MmapMut) -> Self {
         Cursor {
             data: CursorData::MmapMut(data),
             position: 0,
@@ -62,17 +62,15 @@ impl<'a> Cursor<'a> {
     }
 
     pub fn get_range(&self, range: Range<usize>) -> &'a [u8] {
-        let data = match self.data {
+        match self.data {
             CursorData::Raw(data) => &data[range],
             CursorData::Mmap(data) => &data[range],
             CursorData::MmapMut(data) => &data[range],
-        };
-
-        data
+        }
     }
 
     pub fn peek(&self, size: usize) -> Result<&'a [u8], CursorError> {
-        if self.position + size > self.len {
+        if size > self.len.saturating_sub(self.position) {
             return Err(CursorError::InvalidRange);
         }
 
@@ -90,11 +88,11 @@ impl<'a> Cursor<'a> {
     }
 
     pub fn set_back(&mut self, steps: usize) {
-        self.position = self.position - steps;
+        self.position -= steps;
     }
 
     pub fn forward(&mut self, steps: usize) {
-        self.position = self.position + steps;
+        self.position += steps;
     }
 
     pub fn move_to(&mut self, pos: usize) {
@@ -1,148 +1,494 @@
 
 use std::path::{Path, PathBuf};
-use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::SystemTime;
 use memmap2::MmapMut;
 use tokio::fs::{File, OpenOptions};
+use tokio::sync::Notify;
 use uuid::Uuid;
 use crate::cursor::Cursor;
+use crate::disk_iterator::DiskIterator;
+use crate::disk_reader::DiskReader;
 use crate::disk_metadata::{DiskMetadata, DiskMetadataV1};
-use crate::{DiskError, U64_SIZE};
+use crate::durability::{Durability, GroupCommit};
+use crate::header::{DiskHeader, DISK_HEADER_PREFIX_SIZE};
+use crate::record::{RecordHeader, RecordLocation, RECORD_FLAG_HOLE, RECORD_FLAG_PENDING, RECORD_HEADER_SIZE};
+use crate::reservation::Reservation;
+use crate::writer_lock::WriterLock;
+use crate::DiskError;
 use crate::utils::get_created_at;
 
 #[derive(Clone)]
 pub struct DiskConf<P: AsRef<Path> + Clone> {
+    /// Size of new disks. Existing disks keep the capacity stored in their header,
+    /// asking for less is refused rather than truncating data, see `Disk::grow`.
     pub capacity: u64,
     pub max_items: u64,
+    /// Size of the reserved header region of new disks, see `DEFAULT_HEADER_SIZE`.
+    /// Existing disks keep the size they were created with.
+    pub header_size: u64,
+    /// When appended records are written back, see `Durability`
+    pub durability: Durability,
     pub disk_file_path: P
 }
 
+/// What was found when a disk was opened.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RecoveryReport {
+    /// Number of committed records found
+    pub records: u64,
+    /// Bytes taken by those records, headers included
+    pub bytes: u64,
+    /// Offset where appending resumes
+    pub write_offset: usize,
+    /// Reservations that were never committed
+    pub holes: u64,
+    /// Record count stored in the header by the last flush
+    pub persisted_records: u64,
+    /// Committed record found at the highest offset
+    pub last_record: Option<RecordLocation>,
+    /// Pid of a previous writer that died while holding the disk
+    pub stale_writer: Option<u32>,
+    /// Offset of a record whose checksum did not match while committed records
+    /// follow it. Nothing past it is read, and `Disk::open` refuses to append over it.
+    pub corrupted_at: Option<usize>,
+    /// Offset of a record whose checksum did not match with nothing committed
+    /// after it, a write torn by a crash. It is dropped and appending resumes there.
+    pub torn_at: Option<usize>,
+}
+
+/// Keeps `Disk::busy` incremented for as long as a write is in flight
+pub(crate) struct WriteGuard<'a> {
+    busy: &'a AtomicUsize,
+    idle: &'a Notify,
+}
+
+impl<'a> WriteGuard<'a> {
+    fn new(busy: &'a AtomicUsize, idle: &'a Notify) -> Self {
+        busy.fetch_add(1, Ordering::SeqCst);
+        Self { busy, idle }
+    }
+}
+
+impl Drop for WriteGuard<'_> {
+    fn drop(&mut self) {
+        if self.busy.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.idle.notify_waiters();
+        }
+    }
+}
+
 pub struct Disk {
     pub id: Uuid,
     pub capacity: u64,
     pub max_items: u64,
     pub path: PathBuf,
-    mmap: MmapMut,
+    /// Shared with the blocking threads that flush it
+    mmap: Arc<MmapMut>,
     write_offset: AtomicUsize,
     locked: AtomicBool,
     pub busy: AtomicUsize, // Tracks the number of active writes,
+    /// Signalled when `busy` drops to zero
+    idle: Notify,
+    items: AtomicU64,
+    /// Records committed so far, `items` also counts pending reservations
+    committed: AtomicU64,
+    /// Offset of the committed record furthest into the disk, 0 if there is none
+    last_committed: AtomicUsize,
     metadata: DiskMetadata,
+    /// Header as last written, updates are serialized through it
+    header: Mutex<DiskHeader>,
+    /// Flushes appended records as the `Durability` of the disk says
+    group_commit: GroupCommit,
+    /// Declared before `file` so its pid file is removed while the lock is still held
+    _writer_lock: WriterLock,
+    /// Kept open for the lifetime of the mapping, holds the writer lock
     file: File,
-    metadata_size: u64
+    header_size: u64,
+    recovery: RecoveryReport,
 }
 
-/// Initialized flag + Locked flag + Metadata Length
-pub const COMMIT_LOG_INITIAL_HEADER_SIZE: usize = 1 + 1 + 8;
-
-
-/// | Byte Range | Description                | Details                      |
-/// |------------|----------------------------|------------------------------|
-/// | 0          | Initialized flag (1 byte) | 0 = uninitialized, 1 = initialized |
-/// | 1          | Locked flag (1 byte)      | 0 = Unlocked, 1 = Locked |
-/// | 2-10       | Metadata Length (8 bytes) | Legnth of associated metadata in bytes |
-/// | 10...      | Metadata payload (variable) | The actual metadata payload |
+/// A file made of a reserved header region (see `DiskHeader`) followed by records.
 impl Disk {
-    pub async fn new<P: AsRef<Path> + Clone>(opts: DiskConf<P>) -> Self {
-        let DiskConf { disk_file_path, capacity, max_items } = opts;
+    pub async fn open<P: AsRef<Path> + Clone>(opts: DiskConf<P>) -> Result<Self, DiskError> {
+        let DiskConf { disk_file_path, capacity, max_items, header_size, durability } = opts;
+        let path = disk_file_path.as_ref();
 
         let file = OpenOptions::new()
             .read(true)
             .write(true)
             .create(true)
-            .open(&disk_file_path)
+            .truncate(false)
+            .open(path)
             .await
-            .unwrap();
+            .map_err(|e| DiskError::io(path, e))?;
+
+        // Only one writer at a time, across processes
+        let (mut file, writer_lock, stale_writer) = WriterLock::acquire(file, path).await?;
+
+        // Make sure the file is ours before resizing it
+        let region = DiskHeader::read_region(&mut file, header_size).await.map_err(|e| DiskError::io(path, e))?;
+        let capacity = match DiskHeader::probe(&region)? {
+            Some(header) if capacity < header.capacity => {
+                return Err(DiskError::CapacityShrink {
+                    current: header.capacity,
+                    requested: capacity,
+                });
+            }
+            Some(header) => header.capacity,
+            None => capacity,
+        };
 
-        file.set_len(capacity).await.unwrap();
+        // Only ever extend, whatever lies past the capacity was never written to
+        let file_len = file.metadata().await.map_err(|e| DiskError::io(path, e))?.len();
+        if file_len < capacity {
+            file.set_len(capacity).await.map_err(|e| DiskError::io(path, e))?;
+        }
 
         // Memory-map the file
-        let mut mmap = unsafe { MmapMut::map_mut(&file).unwrap() };
+        let mut mmap = unsafe { MmapMut::map_mut(&file).map_err(|e| DiskError::io(path, e))? };
+
+        let (header, metadata) = Self::read_header(&mut mmap, header_size, capacity)?;
 
-        let (locked, metadata, metadata_size) = Self::read_metadata(&mut mmap);
+        let data_start = header.header_size as usize;
+        let (recovery, pending, corruption) = Self::recover(Cursor::mmap_mut(&mmap), data_start, header.record_count);
+        let recovery = RecoveryReport { stale_writer, ..recovery };
 
-        let write_offset_begin_at = COMMIT_LOG_INITIAL_HEADER_SIZE + metadata_size;
+        // Appending from there would overwrite whatever follows the damaged record
+        if let Some(e) = corruption {
+            return Err(e);
+        }
 
-        Self {
+        // Leftovers of the torn record must not pass for records once appending resumes
+        if let Some(offset) = recovery.torn_at {
+            let length = RecordHeader::from_bytes(&mmap[offset..offset + RECORD_HEADER_SIZE]).length as usize;
+            let end = (offset + RECORD_HEADER_SIZE).saturating_add(length).min(mmap.len());
+            mmap[offset..end].fill(0);
+        }
+
+        // Reservations of the previous writer will never be committed
+        for location in pending {
+            let hole = RecordHeader::new(location.length as u32, RECORD_FLAG_HOLE);
+            mmap[location.offset..location.payload_offset()].copy_from_slice(&hole.to_bytes());
+        }
+
+        let mmap = Arc::new(mmap);
+
+        Ok(Self {
             id: Uuid::new_v4(),
+            group_commit: GroupCommit::spawn(mmap.clone(), durability),
             mmap,
-            write_offset: AtomicUsize::new(write_offset_begin_at), // It starts from 2 because [initialized, locked]
+            write_offset: AtomicUsize::new(recovery.write_offset),
             capacity,
-            locked: AtomicBool::from(locked),
+            locked: AtomicBool::from(header.locked),
             busy: AtomicUsize::new(0),
+            idle: Notify::new(),
+            items: AtomicU64::new(recovery.records),
+            committed: AtomicU64::new(recovery.records),
+            last_committed: AtomicUsize::new(recovery.last_record.map_or(0, |l| l.offset)),
             path: disk_file_path.as_ref().to_path_buf(),
             max_items,
             metadata,
+            header: Mutex::new(header),
+            _writer_lock: writer_lock,
             file,
-            metadata_size: metadata_size as u64
+            header_size: header.header_size,
+            recovery,
+        })
+    }
+
+    /// Open the disk at `path` for reading only, see `DiskReader`
+    pub async fn open_read_only<P: AsRef<Path>>(path: P) -> Result<DiskReader, DiskError> {
+        DiskReader::open(path).await
+    }
+
+    /// Scan the committed records to find where the previous writer stopped,
+    /// so reopening never overwrites existing data.
+    ///
+    /// Reservations still pending are skipped as holes and handed back, the
+    /// records committed after them are kept.
+    ///
+    /// A damaged record is only corruption, handed back as well, when committed
+    /// records follow it. Otherwise it is the torn tail of the log.
+    pub(crate) fn recover(
+        cursor: Cursor,
+        data_start: usize,
+        persisted_records: u64,
+    ) -> (RecoveryReport, Vec<RecordLocation>, Option<DiskError>) {
+        let end = cursor.len;
+        let bytes = cursor.get_range(0..end);
+        let mut iter = DiskIterator::new(cursor.set_starting_pos(data_start), end).skip_pending();
+        let mut records = 0;
+        let mut corruption = None;
+        let mut torn_at = None;
+        let mut last_record = None;
+
+        for record in iter.by_ref() {
+            match record {
+                Ok(record) => {
+                    records += 1;
+                    last_record = Some(record.location);
+                }
+                Err(e @ DiskError::Corrupted { offset, .. }) => match Self::committed_after(bytes, offset) {
+                    true => corruption = Some(e),
+                    false => torn_at = Some(offset),
+                },
+                Err(_) => break,
+            }
         }
+
+        let corrupted_at = match corruption {
+            Some(DiskError::Corrupted { offset, .. }) => Some(offset),
+            _ => None,
+        };
+
+        let write_offset = iter.offset();
+
+        let report = RecoveryReport {
+            records,
+            holes: iter.holes(),
+            bytes: (write_offset - data_start) as u64,
+            write_offset,
+            persisted_records,
+            last_record,
+            stale_writer: None,
+            corrupted_at,
+            torn_at,
+        };
+
+        (report, iter.pending().to_vec(), corruption)
     }
 
-    fn curr_writing_offset(&self) -> usize {
+    /// Whether a committed record follows the damaged one at `offset`
+    fn committed_after(bytes: &[u8], offset: usize) -> bool {
+        let length = RecordHeader::from_bytes(&bytes[offset..offset + RECORD_HEADER_SIZE]).length as usize;
+        let next = (offset + RECORD_HEADER_SIZE).saturating_add(length);
+
+        next < bytes.len()
+            && DiskIterator::new(Cursor::new(bytes).set_starting_pos(next), bytes.len())
+                .skip_pending()
+                .any(|record| record.is_ok())
+    }
+
+    pub fn recovery_report(&self) -> &RecoveryReport {
+        &self.recovery
+    }
+
+    pub fn curr_writing_offset(&self) -> usize {
         self.write_offset.load(Ordering::Relaxed)
     }
 
+    /// Number of records appended so far
+    pub fn items(&self) -> u64 {
+        self.items.load(Ordering::Acquire)
+    }
+
+    pub fn metadata(&self) -> &DiskMetadata {
+        &self.metadata
+    }
+
+    /// Header fields as last written to the file
+    pub fn header(&self) -> DiskHeader {
+        *self.header.lock().unwrap()
+    }
+
+    /// Replace the metadata stored in the header region.
+    /// Records never move, so the new payload has to fit in the region.
+    pub fn rewrite_metadata(&mut self, metadata: DiskMetadata) -> Result<(), DiskError> {
+        let bytes = metadata.to_vec();
+
+        if bytes.len() > self.header().metadata_capacity() {
+            return Err(DiskError::MetadataTooLarge);
+        }
+
+        self.write_header(&bytes, |_| {})?;
+        self.metadata = metadata;
+
+        Ok(())
+    }
+
+    /// Rewrite V1 metadata as V2 in place
+    pub fn upgrade_metadata(&mut self) -> Result<(), DiskError> {
+        if self.metadata.is_v1() {
+            self.rewrite_metadata(DiskMetadata::V2(self.metadata.upgrade()))?;
+        }
+
+        Ok(())
+    }
+
+    /// Offset of the first record, right after the reserved header region
+    pub fn data_start(&self) -> usize {
+        self.header_size as usize
+    }
+
     /// Set the lock state (true for locked, false for unlocked)
-    fn set_locked(&self, locked: bool) -> Result<(), DiskError> {
+    pub fn set_locked(&self, locked: bool) -> Result<(), DiskError> {
         // Update the in-memory AtomicBool
-        self.locked.store(locked, Ordering::Release);
+        self.locked.store(locked, Ordering::SeqCst);
 
-        // Update the mmap to reflect the lock state
-        let lock_flag = if locked { 1u8 } else { 0u8 };
+        self.mmap.flush().map_err(|_| DiskError::InvalidFlushing)?;
 
-        // Unsafe write to mmap with synchronization
-        unsafe {
-            let lock_ptr = self.mmap.as_ptr().add(1) as *mut u8;
-            std::ptr::write(lock_ptr, lock_flag);
-        }
+        self.update_header(|header| {
+            header.locked = locked;
 
-        // Flush the mmap to persist changes
-        self.mmap
-            .flush()
-            .map_err(|_| DiskError::InvalidFlushing)
+            // Unlocking undoes a seal
+            if !locked {
+                header.sealed_at = 0;
+            }
+        })
     }
 
 
-    pub fn reserve_space(&self, size: usize) -> Result<usize, DiskError> {
+    fn reserve_space(&self, size: usize) -> Result<usize, DiskError> {
         // Check if the log is locked before proceeding
         if self.is_locked() {
             return Err(DiskError::Locked);
         }
 
-        // Atomically reserve space
-        let offset = self.write_offset.fetch_add(size, Ordering::SeqCst);
+        // Atomically reserve space, leaving the offset untouched if it doesn't fit
+        self.write_offset
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |offset| {
+                offset
+                    .checked_add(size)
+                    .filter(|end| *end <= self.capacity as usize)
+            })
+            .map_err(|_| DiskError::CapacityReached)
+    }
+
+    /// Extend the file to `new_capacity` bytes and map it again, without closing
+    /// the disk. Taking `&mut self` guarantees nobody holds on to the old mapping.
+    pub async fn grow(&mut self, new_capacity: u64) -> Result<(), DiskError> {
+        if new_capacity < self.capacity {
+            return Err(DiskError::CapacityShrink {
+                current: self.capacity,
+                requested: new_capacity,
+            });
+        }
 
-        if offset + size > self.capacity as usize {
-            Err(DiskError::CapacityReached)
-        } else {
-            Ok(offset)
+        if new_capacity == self.capacity {
+            return Ok(());
         }
+
+        self.flush().await?;
+        self.file
+            .set_len(new_capacity)
+            .await
+            .map_err(|e| DiskError::io(&self.path, e))?;
+        self.mmap = Arc::new(unsafe { MmapMut::map_mut(&self.file).map_err(|e| DiskError::io(&self.path, e))? });
+        self.group_commit.restart(self.mmap.clone());
+
+        // The file is already large enough by the time the header says so
+        self.update_header(|header| header.capacity = new_capacity)?;
+        self.capacity = new_capacity;
+
+        Ok(())
     }
 
-    fn initialize_file(mmap: &mut MmapMut) {
-        mmap[0] = 1u8; // Mark as initialized
-        mmap[1] = 0u8; // Mark as unlocked
-        mmap.flush().expect("Failed to flush mmap during initialization");
+    /// Bytes that can still be reserved, record headers included
+    pub fn remaining_capacity(&self) -> u64 {
+        self.capacity.saturating_sub(self.curr_writing_offset() as u64)
     }
 
-    fn read_metadata(mmap: &mut MmapMut) -> (bool, DiskMetadata, usize) {
-        let mut cursor = Cursor::mmap_mut(mmap);
+    /// Bytes taken by records (reserved or committed), headers included
+    pub fn used_bytes(&self) -> u64 {
+        (self.curr_writing_offset() - self.data_start()) as u64
+    }
 
-        // Read the first two bytes to determine initialization and lock status
-        let initialized_locked_val = cursor.consume(2).expect("Failed to read initialization and lock bytes");
-        let initialized = initialized_locked_val[0] == 1u8;
-        let locked = initialized_locked_val[1] == 1u8;
+    /// Claim a slot for one more record, respecting `max_items`
+    fn reserve_item(&self) -> Result<(), DiskError> {
+        self.items
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |items| {
+                (items < self.max_items).then_some(items + 1)
+            })
+            .map(|_| ())
+            .map_err(|_| DiskError::MaxItemsReached)
+    }
 
-        let (metadata, metadata_size) = if initialized {
-            Self::read_existing_metadata(&mut cursor)
-        } else {
-            Self::initialize_file(mmap);
-            Self::create_and_store_metadata(mmap)
-        };
+    /// Write the next generation of the header, with the current record count
+    /// and last checksum, to the slot that is not in use.
+    ///
+    /// The slot in use is left alone, so if the process dies halfway through
+    /// the next open still finds the previous header intact.
+    fn write_header(&self, metadata: &[u8], update: impl FnOnce(&mut DiskHeader)) -> Result<(), DiskError> {
+        let mut current = self.header.lock().unwrap();
+
+        let mut next = current.next();
+        update(&mut next);
+        next.record_count = self.committed.load(Ordering::SeqCst);
+        next.last_checksum = self.last_checksum();
+        next.metadata_length = metadata.len() as u64;
+
+        let slot = next.slot_bytes(metadata);
+        self.copy_into(&slot, next.slot_offset());
+        self.mmap
+            .flush_range(next.slot_offset(), slot.len())
+            .map_err(|_| DiskError::InvalidFlushing)?;
+
+        *current = next;
+
+        Ok(())
+    }
+
+    /// Same as `write_header`, keeping the current metadata
+    fn update_header(&self, update: impl FnOnce(&mut DiskHeader)) -> Result<(), DiskError> {
+        self.write_header(&self.metadata.to_vec(), update)
+    }
+
+    /// Checksum of the committed record furthest into the disk, 0 if there is none
+    fn last_checksum(&self) -> u32 {
+        match self.last_committed.load(Ordering::SeqCst) {
+            0 => 0,
+            offset => RecordHeader::from_bytes(&self.mmap[offset..offset + RECORD_HEADER_SIZE]).checksum,
+        }
+    }
+
+    /// Remember that the record at `location` was committed
+    pub(crate) fn mark_committed(&self, location: RecordLocation) {
+        self.committed.fetch_add(1, Ordering::SeqCst);
+        self.last_committed.fetch_max(location.offset, Ordering::SeqCst);
+        self.group_commit.committed(location);
+    }
+
+    fn read_header(mmap: &mut MmapMut, header_size: u64, capacity: u64) -> Result<(DiskHeader, DiskMetadata), DiskError> {
+        match Self::parse_header(mmap)? {
+            Some(parsed) => Ok(parsed),
+            None => Self::initialize_file(mmap, header_size, capacity),
+        }
+    }
+
+    /// Header and metadata at the start of `bytes`, `None` if the file was never initialized
+    pub(crate) fn parse_header(bytes: &[u8]) -> Result<Option<(DiskHeader, DiskMetadata)>, DiskError> {
+        // The prefix is enough to tell, `Disk::open` looked at the rest of the
+        // header region before resizing the file
+        if !DiskHeader::has_magic(bytes) {
+            return DiskHeader::probe(&bytes[..bytes.len().min(DISK_HEADER_PREFIX_SIZE)]).map(|_| None);
+        }
+
+        let header = DiskHeader::from_bytes(bytes)?;
+
+        let metadata_length = header.metadata_length as usize;
+
+        if header.header_size > header.capacity
+            || header.capacity > bytes.len() as u64
+            || metadata_length > header.metadata_capacity()
+        {
+            return Err(DiskError::InvalidMetadata);
+        }
+
+        let metadata_bytes = &bytes[header.metadata_offset()..header.metadata_offset() + metadata_length];
 
-        (locked, metadata, metadata_size)
+        let version = *metadata_bytes.first().ok_or(DiskError::InvalidMetadata)?;
+        if !DiskMetadata::is_known_identifier(version) {
+            return Err(DiskError::UnknownMetadataVersion { version });
+        }
+
+        let metadata = DiskMetadata::try_from(metadata_bytes.to_vec())?;
+
+        Ok(Some((header, metadata)))
     }
 
-    fn create_and_store_metadata(mmap: &mut MmapMut) -> (DiskMetadata, usize) {
+    fn initialize_file(mmap: &mut MmapMut, header_size: u64, capacity: u64) -> Result<(DiskHeader, DiskMetadata), DiskError> {
         let metadata = DiskMetadata::V1(DiskMetadataV1 {
             created_at: get_created_at(SystemTime::now())
         });
@@ -150,66 +496,211 @@ impl Disk {
         let metadata_bytes = metadata.to_vec();
         let metadata_length = metadata_bytes.len();
 
-        // Store metadata size and metadata itself
-        mmap[2..10].copy_from_slice(&metadata_length.to_le_bytes());
-        mmap[10..(10 + metadata_length)].copy_from_slice(&metadata_bytes);
-
-        mmap.flush().expect("Failed to flush mmap during metadata creation");
+        if capacity < header_size {
+            return Err(DiskError::CapacityReached);
+        }
 
-        (metadata, metadata_length)
-    }
+        let header = DiskHeader::new(header_size, capacity, metadata_length as u64);
+        if metadata_length > header.metadata_capacity() {
+            return Err(DiskError::MetadataTooLarge);
+        }
 
-    fn read_existing_metadata(cursor: &mut Cursor) -> (DiskMetadata, usize) {
-        // Read metadata size
-        let metadata_size_bytes = cursor.consume(U64_SIZE).expect("Failed to read metadata size");
-        let metadata_size = u64::from_le_bytes(metadata_size_bytes.try_into().expect("Invalid metadata size bytes"));
+        // Slots first, the magic makes the file count as initialized
+        let region = header.to_bytes(&metadata_bytes);
+        mmap[DISK_HEADER_PREFIX_SIZE..header_size as usize].copy_from_slice(&region[DISK_HEADER_PREFIX_SIZE..]);
+        mmap.flush().map_err(|_| DiskError::InvalidFlushing)?;
 
-        // Read metadata
-        let metadata_bytes = cursor
-            .consume(metadata_size as usize)
-            .expect("Failed to read metadata bytes");
+        mmap[..DISK_HEADER_PREFIX_SIZE].copy_from_slice(&region[..DISK_HEADER_PREFIX_SIZE]);
+        mmap.flush().map_err(|_| DiskError::InvalidFlushing)?;
 
-        (DiskMetadata::try_from(metadata_bytes.to_vec()).unwrap(), metadata_size as usize)
+        Ok((header, metadata))
     }
 
     /// Check if the log is locked
     fn is_locked(&self) -> bool {
-        self.locked.load(Ordering::Acquire)
+        self.locked.load(Ordering::SeqCst)
     }
 
-    fn write(&self, data: &[u8], start_at: usize) -> Result<(), DiskError> {
+    /// Register an in-flight write, failing if the disk is locked.
+    /// `seal` waits for every guard handed out here to be dropped.
+    pub(crate) fn begin_write(&self) -> Result<WriteGuard<'_>, DiskError> {
         // Check if the log is locked before proceeding
         if self.is_locked() {
             return Err(DiskError::Locked);
         }
 
         // Indicate the log is busy by incrementing the counter
-        self.busy.fetch_add(1, Ordering::SeqCst);
-
-        let len = data.len();
+        let guard = WriteGuard::new(&self.busy, &self.idle);
 
+        // The lock may have been taken in between, the guard gives the slot back
         if self.is_locked() {
-            self.busy.fetch_sub(1, Ordering::SeqCst); // Decrement on failure
             return Err(DiskError::Locked);
         }
 
+        Ok(guard)
+    }
+
+    /// Raw, unframed write into already reserved space
+    #[cfg(test)]
+    fn write(&self, data: &[u8], start_at: usize) -> Result<(), DiskError> {
+        let _guard = self.begin_write()?;
+        self.copy_into(data, start_at);
+
+        Ok(())
+    }
+
+    pub(crate) fn copy_into(&self, data: &[u8], start_at: usize) {
         unsafe {
             // Access the mmap memory as a raw pointer
             let mmap_ptr = self.mmap.as_ptr() as *mut u8;
 
             // Write data into the reserved region using raw pointer arithmetic
-            std::ptr::copy_nonoverlapping(data.as_ptr(), mmap_ptr.add(start_at), len);
+            std::ptr::copy_nonoverlapping(data.as_ptr(), mmap_ptr.add(start_at), data.len());
         }
+    }
 
-        self.busy.fetch_sub(1, Ordering::SeqCst);
+    /// Store the flags byte at `offset` with release ordering, see `RecordHeader::load`
+    pub(crate) fn publish_flags(&self, offset: usize, flags: u8) {
+        // SAFETY: in bounds of the mapping, `AtomicU8` has the layout of `u8`
+        let flags_byte = unsafe { &*(self.mmap.as_ptr().add(offset) as *const AtomicU8) };
+        flags_byte.store(flags, Ordering::Release);
+    }
 
-        Ok(())
+    /// Claim space for a record of `length` bytes.
+    ///
+    /// Nothing is visible to readers until the returned `Reservation` is committed.
+    pub fn reserve(&self, length: usize) -> Result<Reservation<'_>, DiskError> {
+        u32::try_from(length).map_err(|_| DiskError::RecordTooLarge)?;
+        let guard = self.begin_write()?;
+        self.reserve_item()?;
+
+        let offset = self.reserve_space(RECORD_HEADER_SIZE + length).inspect_err(|_| {
+            self.items.fetch_sub(1, Ordering::SeqCst);
+        })?;
+
+        // Lets recovery skip the space if the process dies before the commit
+        let pending = RecordHeader::new(length as u32, RECORD_FLAG_PENDING);
+        self.copy_into(&pending.to_bytes(), offset);
+        let location = RecordLocation { offset, length };
+        self.group_commit.reserved(location);
+
+        Ok(Reservation::new(self, guard, location))
+    }
+
+    /// Append a self-describing record (header + payload) to the disk.
+    ///
+    /// The payload is written first and the header last, so a record is only
+    /// seen as committed once all of its bytes are in place.
+    pub fn append(&self, data: &[u8]) -> Result<RecordLocation, DiskError> {
+        self.reserve(data.len())?.commit(data)
+    }
+
+    /// Same as `append`, only returning once the record is durable
+    pub async fn append_durable(&self, data: &[u8]) -> Result<RecordLocation, DiskError> {
+        let location = self.append(data)?;
+        self.wait_durable(location).await?;
+
+        Ok(location)
+    }
+
+    /// Wait until the committed record at `location` was written back to the file.
+    ///
+    /// Records are flushed as the `Durability` of the disk says, in batches, so
+    /// concurrent waiters share a single flush.
+    pub async fn wait_durable(&self, location: RecordLocation) -> Result<(), DiskError> {
+        self.group_commit.wait(location).await
+    }
+
+    /// Mark a reservation that was never committed as a hole, so readers skip it
+    pub(crate) fn abandon(&self, location: RecordLocation) {
+        let header = RecordHeader::new(location.length as u32, RECORD_FLAG_HOLE);
+        self.copy_into(&header.to_bytes(), location.offset);
+        self.group_commit.reserved(location);
+        self.items.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    /// Read the payload of the record at `location` without copying it
+    pub fn read(&self, location: RecordLocation) -> Result<&[u8], DiskError> {
+        if location.offset < self.data_start() {
+            return Err(DiskError::InvalidLocation);
+        }
+
+        let high_water_mark = self.curr_writing_offset().min(self.mmap.len());
+        let mut cursor = Cursor::mmap_mut(&self.mmap);
+        cursor.len = high_water_mark;
+
+        location.read(cursor)
+    }
+
+    /// Same as `read`, but hands back an owned copy of the payload
+    pub fn read_owned(&self, location: RecordLocation) -> Result<Vec<u8>, DiskError> {
+        self.read(location).map(|payload| payload.to_vec())
+    }
+
+    /// Iterate over every committed record, stopping at the first unwritten or torn region.
+    pub fn iter(&self) -> DiskIterator<'_> {
+        let cursor = Cursor::mmap_mut(&self.mmap).set_starting_pos(self.data_start());
+        DiskIterator::new(cursor, self.mmap.len())
+    }
+
+    /// Lock the disk for good: new writes are rejected, in-flight ones are
+    /// allowed to finish and everything is flushed before returning.
+    pub async fn seal(&self) -> Result<(), DiskError> {
+        self.set_locked(true)?;
+
+        loop {
+            // Registered before looking at `busy`, so the last guard can't slip by
+            let idle = self.idle.notified();
+            tokio::pin!(idle);
+            idle.as_mut().enable();
+
+            if self.busy.load(Ordering::SeqCst) == 0 {
+                break;
+            }
+            idle.await;
+        }
+
+        self.group_commit.flush(&self.mmap).await?;
+
+        let sealed_at = get_created_at(SystemTime::now());
+        self.update_header(|header| header.sealed_at = sealed_at)
     }
 
-    pub fn flush(&self) -> Result<(), DiskError> {
-        // Mark the log as no longer busy
-        self.busy.fetch_sub(1, Ordering::SeqCst);
-        self.mmap.flush().map_err(|_| DiskError::InvalidFlushing)
+    pub fn is_sealed(&self) -> bool {
+        self.is_locked()
+    }
+
+    /// Write back every record and store the record count in the header.
+    ///
+    /// Writing back the records can take a while on a large disk, so it runs
+    /// on a blocking thread and the runtime keeps going meanwhile.
+    /// Whoever waits for a record with `wait_durable` is acknowledged as well.
+    pub async fn flush(&self) -> Result<(), DiskError> {
+        self.group_commit.flush(&self.mmap).await?;
+        self.update_header(|_| {})
+    }
+
+    /// Same as `flush`, blocking the calling thread
+    pub fn blocking_flush(&self) -> Result<(), DiskError> {
+        self.mmap.flush().map_err(|_| DiskError::InvalidFlushing)?;
+        self.update_header(|_| {})
+    }
+
+    /// Write back `len` bytes starting at `offset` and wait for it, the header
+    /// is left as is. Cheaper than `flush` when only a few records changed.
+    pub fn flush_range(&self, offset: usize, len: usize) -> Result<(), DiskError> {
+        if offset.checked_add(len).is_none_or(|end| end > self.mmap.len()) {
+            return Err(DiskError::InvalidLocation);
+        }
+
+        self.mmap
+            .flush_range(offset, len)
+            .map_err(|_| DiskError::InvalidFlushing)
+    }
+
+    /// Start writing back every dirty page without waiting for it to finish
+    pub fn flush_async(&self) -> Result<(), DiskError> {
+        self.mmap.flush_async().map_err(|_| DiskError::InvalidFlushing)
     }
 }
 
@@ -221,41 +712,112 @@ mod disk_tests {
     use std::time::Duration;
     use tokio::time::sleep;
     use crate::disk::{Disk, DiskConf};
+    use crate::disk_metadata::DiskMetadata;
+    use crate::header::{DiskHeader, DEFAULT_HEADER_SIZE, DISK_FORMAT_VERSION};
+    use crate::record::{RecordHeader, RecordLocation, RECORD_HEADER_SIZE};
     use crate::DiskError;
-    use crate::utils::test_utils::get_file;
+    use crate::utils::test_utils::{disk_conf, get_file, TEST_HEADER_SIZE};
 
     #[tokio::test]
     pub async fn test_disk_creation() {
         let fake_partial_folder_path = get_file(None, true);
 
         let conf = DiskConf {
-            capacity: 1024,
+            capacity: 8192,
             max_items: 1,
-            disk_file_path: fake_partial_folder_path.clone(),
+            header_size: DEFAULT_HEADER_SIZE,
+            ..disk_conf(fake_partial_folder_path.clone())
         };
 
-        let disk = Disk::new(conf.clone()).await;
-        assert_eq!(disk.locked.load(Ordering::Acquire), false);
-        // COMMIT_LOG_INITIAL_HEADER_SIZE + 9 (9 = metadata size)
-        assert_eq!(disk.write_offset.load(Ordering::Acquire), 19);
+        let disk = Disk::open(conf.clone()).await.unwrap();
+        assert!(!disk.locked.load(Ordering::Acquire));
+        // Data starts right after the reserved header region
+        assert_eq!(disk.write_offset.load(Ordering::Acquire), 4096);
         assert!(disk.metadata.is_v1());
+
+        let header = disk.header();
+        assert_eq!(header.format_version, DISK_FORMAT_VERSION);
+        assert_eq!(header.header_size, DEFAULT_HEADER_SIZE);
+        assert_eq!(header.metadata_length, 9);
+        assert_eq!(header.sealed_at, 0);
+        let created_at = disk.metadata.as_v1().unwrap().created_at;
+        drop(disk);
+
         sleep(Duration::from_secs(2)).await;
-        let disk_2 = Disk::new(conf).await;
-        assert_eq!(disk_2.metadata.as_v1().unwrap().created_at, disk.metadata.as_v1().unwrap().created_at);
+        let disk_2 = Disk::open(conf).await.unwrap();
+        assert_eq!(disk_2.metadata.as_v1().unwrap().created_at, created_at);
+    }
+
+    #[tokio::test]
+    pub async fn test_rewrite_metadata() {
+        let path = get_file(None, true);
+        let conf = DiskConf {
+            capacity: 8192,
+            max_items: 16,
+            header_size: DEFAULT_HEADER_SIZE,
+            ..disk_conf(path.clone())
+        };
+
+        let mut disk = Disk::open(conf.clone()).await.unwrap();
+        let location = disk.append(b"record").unwrap();
+        let created_at = disk.metadata().created_at();
+
+        // The metadata grows inside the header region, records stay where they are
+        let mut metadata = disk.metadata().upgrade();
+        metadata.node_id = Some(String::from("node-1"));
+        metadata.labels.insert(String::from("env"), String::from("prod"));
+        disk.rewrite_metadata(DiskMetadata::V2(metadata.clone())).unwrap();
+        assert_eq!(disk.read(location).unwrap(), b"record");
+        drop(disk);
+
+        let mut disk = Disk::open(conf.clone()).await.unwrap();
+        assert_eq!(disk.metadata().as_v2(), Some(&metadata));
+        assert_eq!(disk.read(location).unwrap(), b"record");
+
+        // Anything larger than the header region is rejected
+        metadata.dataset_id = Some("x".repeat(DEFAULT_HEADER_SIZE as usize));
+        assert_eq!(disk.rewrite_metadata(DiskMetadata::V2(metadata.clone())), Err(DiskError::MetadataTooLarge));
+
+        // Shrinking leaves no trace of the previous payload
+        metadata.dataset_id = None;
+        metadata.labels.clear();
+        disk.rewrite_metadata(DiskMetadata::V2(metadata.clone())).unwrap();
+        drop(disk);
+
+        let disk = Disk::open(conf).await.unwrap();
+        assert_eq!(disk.metadata().as_v2(), Some(&metadata));
+        assert_eq!(disk.metadata().created_at(), created_at);
+        assert_eq!(disk.read(location).unwrap(), b"record");
+        let header = disk.header();
+        let metadata_end = header.metadata_offset() + header.metadata_length as usize;
+        assert!(disk.mmap[metadata_end..header.slot_offset() + header.slot_size()].iter().all(|b| *b == 0));
+    }
+
+    #[tokio::test]
+    pub async fn test_upgrade_metadata_with_records() {
+        let conf = disk_conf(get_file(None, true));
+
+        let mut disk = Disk::open(conf.clone()).await.unwrap();
+        let location = disk.append(b"record").unwrap();
+        disk.upgrade_metadata().unwrap();
+        assert!(disk.metadata().is_v2());
+        drop(disk);
+
+        let disk = Disk::open(conf).await.unwrap();
+        assert!(disk.metadata().is_v2());
+        assert_eq!(disk.read(location).unwrap(), b"record");
     }
 
     #[tokio::test]
     pub async fn test_concurrency_commit_log() {
-        let log = get_disk(None).await;
+        let log = get_disk(Some(4096)).await;
         let log = Arc::new(log);
         let handles: Vec<_> = (0..100)
             .map(|i| {
                 let log = log.clone();
                 thread::spawn(move || {
                     let entry = format!("{}", i);
-                    let data = entry.as_bytes();
-                    let offset = log.reserve_space(data.len()).unwrap();
-                    log.write(&data, offset).unwrap();
+                    log.append(entry.as_bytes()).unwrap();
                 })
             })
             .collect();
@@ -264,27 +826,24 @@ mod disk_tests {
             handle.join().unwrap();
         }
 
-        log.flush().unwrap();
+        log.flush().await.unwrap();
         println!("All threads have finished writing.");
-        let log = Disk::new(DiskConf {
-            capacity: log.capacity,
-            max_items: log.max_items,
-            disk_file_path: log.path.clone(),
-        }).await;
-
-        // let mut cursor = log.get_cursor();
-        // let iter = CommitLogIterator::new(&mut cursor);
-        // let mut items: Vec<String> = iter
-        //     .map(|e| {
-        //         String::from_utf8(e.as_valid().unwrap().data.as_raw().unwrap().to_owned()).unwrap()
-        //     })
-        //     .collect();
-        // items.sort();
-        // assert_eq!(items.len(), 100);
-        // assert_eq!(items[0], "0");
-        // assert_eq!(items[99], "99");
-        //
-        // let _ = std::fs::remove_file(fake_partial_folder_path);
+        let (capacity, max_items, path) = (log.capacity, log.max_items, log.path.clone());
+        drop(log);
+
+        let log = Disk::open(DiskConf { capacity, max_items, ..disk_conf(path) }).await.unwrap();
+
+        let mut items: Vec<String> = log
+            .iter()
+            .map(|e| String::from_utf8(e.unwrap().payload.to_vec()).unwrap())
+            .collect();
+        items.sort();
+        assert_eq!(items.len(), 100);
+        assert_eq!(items[0], "0");
+        assert_eq!(items[99], "99");
+        assert_eq!(log.recovery_report().records, 100);
+
+        let _ = std::fs::remove_file(&log.path);
     }
 
     #[tokio::test]
@@ -312,21 +871,553 @@ mod disk_tests {
         log.set_locked(false).unwrap();
 
         // Write to the log after unlocking
-        let entry_data = vec![9, 10, 11, 12];
+        let entry_data = [9, 10, 11, 12];
         let entry_space = log.reserve_space(entry_data.len()).unwrap();
         assert!(log.write(&entry, entry_space).is_ok());
     }
 
+    #[tokio::test]
+    async fn test_append_writes_framed_records() {
+        let disk = get_disk(None).await;
+
+        let first = disk.append(b"hello").unwrap();
+        let second = disk.append(b"world!").unwrap();
+
+        assert_eq!(first.offset, disk.data_start());
+        assert_eq!(first.length, 5);
+        assert_eq!(second.offset, first.offset + first.size());
+        assert_eq!(second.length, 6);
+        assert_eq!(disk.curr_writing_offset(), second.offset + second.size());
+
+        let header = RecordHeader::from_bytes(&disk.mmap[second.offset..second.payload_offset()]);
+        assert_eq!(header.length, 6);
+        assert!(header.is_committed());
+        assert_eq!(header.checksum, RecordHeader::checksum(6, b"world!"));
+        assert_eq!(&disk.mmap[second.payload_offset()..second.payload_offset() + second.length], b"world!");
+    }
+
+    #[tokio::test]
+    async fn test_reopen_resumes_after_last_record() {
+        let disk = get_disk(None).await;
+        let first = disk.append(b"first").unwrap();
+        let second = disk.append(b"second").unwrap();
+        disk.flush().await.unwrap();
+        let (capacity, max_items, path) = (disk.capacity, disk.max_items, disk.path.clone());
+        drop(disk);
+
+        let reopened = Disk::open(DiskConf { capacity, max_items, ..disk_conf(path) }).await.unwrap();
+
+        let report = reopened.recovery_report();
+        assert_eq!(report.records, 2);
+        assert_eq!(report.persisted_records, 2);
+        assert_eq!(report.bytes, (first.size() + second.size()) as u64);
+        assert_eq!(report.write_offset, second.offset + second.size());
+
+        let third = reopened.append(b"third").unwrap();
+        assert_eq!(third.offset, report.write_offset);
+
+        let payloads: Vec<&[u8]> = reopened.iter().map(|r| r.unwrap().payload).collect();
+        assert_eq!(payloads, vec![b"first".as_slice(), b"second", b"third"]);
+    }
+
+    #[tokio::test]
+    async fn test_open_refuses_corrupted_disk() {
+        let disk = get_disk(None).await;
+        let first = disk.append(b"first").unwrap();
+        let second = disk.append(b"second").unwrap();
+        disk.flush().await.unwrap();
+        let (capacity, max_items, path) = (disk.capacity, disk.max_items, disk.path.clone());
+        drop(disk);
+
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[first.payload_offset()] ^= 0xFF;
+        std::fs::write(&path, &bytes).unwrap();
+
+        let reopened = Disk::open(DiskConf { capacity, max_items, ..disk_conf(path.clone()) }).await;
+        assert!(matches!(reopened, Err(DiskError::Corrupted { offset, .. }) if offset == first.offset));
+        assert_eq!(std::fs::read(&path).unwrap(), bytes);
+
+        // Still readable up to the damage
+        let reader = Disk::open_read_only(&path).await.unwrap();
+        assert_eq!(reader.recovery_report().corrupted_at, Some(first.offset));
+        assert_eq!(reader.read(second), Err(DiskError::InvalidLocation));
+    }
+
+    #[tokio::test]
+    async fn test_reopen_drops_torn_tail() {
+        let disk = get_disk(None).await;
+        let first = disk.append(b"first").unwrap();
+        let second = disk.append(b"second").unwrap();
+        disk.flush().await.unwrap();
+        let (capacity, max_items, path) = (disk.capacity, disk.max_items, disk.path.clone());
+        drop(disk);
+
+        // The process died while the last record was being written
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[second.payload_offset()] ^= 0xFF;
+        std::fs::write(&path, &bytes).unwrap();
+
+        let reopened = Disk::open(DiskConf { capacity, max_items, ..disk_conf(path) }).await.unwrap();
+        let report = reopened.recovery_report();
+        assert_eq!(report.records, 1);
+        assert_eq!(report.torn_at, Some(second.offset));
+        assert_eq!(report.corrupted_at, None);
+        assert_eq!(report.write_offset, second.offset);
+
+        let third = reopened.append(b"third").unwrap();
+        assert_eq!(third.offset, second.offset);
+        let payloads: Vec<_> = reopened.iter().map(|r| r.unwrap().payload.to_vec()).collect();
+        assert_eq!(payloads, vec![b"first".to_vec(), b"third".to_vec()]);
+        assert_eq!(reopened.read(first).unwrap(), b"first");
+    }
+
+    #[tokio::test]
+    async fn test_recovery_skips_pending_reservations() {
+        let disk = get_disk(None).await;
+        let first = disk.append(b"first").unwrap();
+        let pending = disk.reserve(4).unwrap();
+        let durable = disk.append_durable(b"durable").await.unwrap();
+
+        // What the file looks like if the process dies right now
+        let crashed = get_file(None, true);
+        std::fs::copy(&disk.path, &crashed).unwrap();
+        let pending_location = pending.location();
+        drop(pending);
+
+        let reopened = Disk::open(DiskConf {
+            capacity: disk.capacity,
+            max_items: disk.max_items,
+            ..disk_conf(crashed)
+        }).await.unwrap();
+
+        let report = reopened.recovery_report();
+        assert_eq!(report.records, 2);
+        assert_eq!(report.holes, 1);
+        assert_eq!(report.write_offset, durable.offset + durable.size());
+        assert_eq!(reopened.read(durable).unwrap(), b"durable");
+        assert_eq!(reopened.read(pending_location), Err(DiskError::InvalidLocation));
+
+        // The pending reservation is a hole for good, nothing stops at it
+        let last = reopened.append(b"last").unwrap();
+        assert_eq!(last.offset, report.write_offset);
+        let payloads: Vec<&[u8]> = reopened.iter().map(|r| r.unwrap().payload).collect();
+        assert_eq!(payloads, vec![b"first".as_slice(), b"durable", b"last"]);
+        assert_eq!(reopened.read(first).unwrap(), b"first");
+    }
+
+    #[tokio::test]
+    async fn test_open_reports_errors_instead_of_panicking() {
+        let folder = get_file(None, true);
+        std::fs::create_dir_all(&folder).unwrap();
+
+        // A directory can't be opened as a disk
+        let result = Disk::open(disk_conf(folder.clone())).await;
+        match result {
+            Err(DiskError::Io { path, .. }) => assert_eq!(path, folder),
+            _ => panic!("Expected an I/O error"),
+        }
+
+        // Initialized header pointing to an unknown metadata version
+        let path = get_file(None, true);
+        let mut bytes = vec![0u8; 1024];
+        let region = DiskHeader::new(TEST_HEADER_SIZE, 1024, 1).to_bytes(&[42]);
+        bytes[..region.len()].copy_from_slice(&region);
+        std::fs::write(&path, &bytes).unwrap();
+
+        let result = Disk::open(disk_conf(path.clone())).await;
+        assert!(matches!(result, Err(DiskError::UnknownMetadataVersion { version: 42 })));
+
+        // Header region larger than the whole disk
+        let region = DiskHeader::new(TEST_HEADER_SIZE, 64, 1).to_bytes(&[0]);
+        bytes[..region.len()].copy_from_slice(&region);
+        std::fs::write(&path, &bytes).unwrap();
+
+        let result = Disk::open(disk_conf(path)).await;
+        assert!(matches!(result, Err(DiskError::InvalidMetadata)));
+    }
+
+    #[tokio::test]
+    async fn test_refuses_foreign_files() {
+        // Left untouched, not even resized
+        let path = get_file(None, true);
+        std::fs::write(&path, b"some notes that are not a disk").unwrap();
+        assert_eq!(Disk::open(disk_conf(path.clone())).await.err(), Some(DiskError::NotADiskFile));
+        assert_eq!(std::fs::read(&path).unwrap(), b"some notes that are not a disk");
+
+        // Starting with zeros is not enough to pass for a new disk
+        let mut foreign = vec![0u8; 64];
+        foreign.extend_from_slice(b"more notes");
+        std::fs::write(&path, &foreign).unwrap();
+        assert_eq!(Disk::open(disk_conf(path.clone())).await.err(), Some(DiskError::NotADiskFile));
+        assert_eq!(std::fs::read(&path).unwrap(), foreign);
+
+        // A disk from a newer version
+        let path = get_file(None, true);
+        let mut header = DiskHeader::new(TEST_HEADER_SIZE, 1024, 9);
+        header.format_version = DISK_FORMAT_VERSION + 1;
+        std::fs::write(&path, header.to_bytes(&[0u8; 9])).unwrap();
+        assert_eq!(
+            Disk::open(disk_conf(path.clone())).await.err(),
+            Some(DiskError::UnsupportedVersion { version: DISK_FORMAT_VERSION + 1 })
+        );
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), TEST_HEADER_SIZE);
+
+        // Empty and zeroed files are fair game
+        for bytes in [vec![], vec![0u8; 512]] {
+            let path = get_file(None, true);
+            std::fs::write(&path, bytes).unwrap();
+            let disk = Disk::open(disk_conf(path)).await.unwrap();
+            assert_eq!(disk.data_start(), TEST_HEADER_SIZE as usize);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reopen_never_shrinks() {
+        let path = get_file(None, true);
+        let conf = |capacity| DiskConf { capacity, ..disk_conf(path.clone()) };
+
+        let disk = Disk::open(conf(1024)).await.unwrap();
+        let location = disk.append(b"record").unwrap();
+        disk.flush().await.unwrap();
+        drop(disk);
+
+        assert_eq!(
+            Disk::open(conf(512)).await.err(),
+            Some(DiskError::CapacityShrink { current: 1024, requested: 512 })
+        );
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 1024);
+
+        // Asking for more does not grow it either
+        let disk = Disk::open(conf(4096)).await.unwrap();
+        assert_eq!(disk.capacity, 1024);
+        assert_eq!(disk.header().capacity, 1024);
+        assert_eq!(disk.read(location).unwrap(), b"record");
+    }
+
+    #[tokio::test]
+    async fn test_grow() {
+        let mut disk = get_disk(Some(200)).await;
+        let first = disk.append(b"first").unwrap();
+        assert_eq!(disk.append(&[0u8; 64]), Err(DiskError::CapacityReached));
+
+        assert!(matches!(disk.grow(64).await, Err(DiskError::CapacityShrink { current: 200, requested: 64 })));
+        disk.grow(400).await.unwrap();
+        assert_eq!(disk.capacity, 400);
+        assert_eq!(disk.header().capacity, 400);
+
+        let second = disk.append(&[1u8; 64]).unwrap();
+        assert_eq!(disk.read(first).unwrap(), b"first");
+        disk.flush().await.unwrap();
+        let (max_items, path) = (disk.max_items, disk.path.clone());
+        drop(disk);
+
+        let reopened = Disk::open(DiskConf { capacity: 200, max_items, ..disk_conf(path.clone()) }).await;
+        assert!(matches!(reopened, Err(DiskError::CapacityShrink { current: 400, .. })));
+
+        let reopened = Disk::open(DiskConf { capacity: 400, max_items, ..disk_conf(path) }).await.unwrap();
+        assert_eq!(reopened.read(second).unwrap(), vec![1u8; 64]);
+        assert_eq!(reopened.recovery_report().records, 2);
+    }
+
+    #[tokio::test]
+    async fn test_append_respects_max_items() {
+        let path = get_file(None, true);
+        let conf = DiskConf { max_items: 2, ..disk_conf(path) };
+
+        let disk = Disk::open(conf.clone()).await.unwrap();
+        disk.append(b"one").unwrap();
+        disk.append(b"two").unwrap();
+        assert_eq!(disk.append(b"three"), Err(DiskError::MaxItemsReached));
+        assert_eq!(disk.items(), 2);
+        disk.flush().await.unwrap();
+        drop(disk);
+
+        let reopened = Disk::open(conf).await.unwrap();
+        assert_eq!(reopened.items(), 2);
+        assert_eq!(reopened.append(b"three"), Err(DiskError::MaxItemsReached));
+    }
+
+    #[tokio::test]
+    async fn test_read_by_location() {
+        let disk = get_disk(None).await;
+        let first = disk.append(b"first").unwrap();
+        let second = disk.append(b"second").unwrap();
+
+        assert_eq!(disk.read(first).unwrap(), b"first");
+        assert_eq!(disk.read_owned(second).unwrap(), b"second".to_vec());
+
+        // Wrong length, misaligned offset, inside the header and past the high-water mark
+        let wrong_length = RecordLocation { length: 3, ..first };
+        let misaligned = RecordLocation { offset: first.offset + 1, ..first };
+        let in_header = RecordLocation { offset: 0, ..first };
+        let past_end = RecordLocation { offset: second.offset + second.size(), ..first };
+        for location in [wrong_length, misaligned, in_header, past_end] {
+            assert_eq!(disk.read(location), Err(DiskError::InvalidLocation));
+        }
+
+        // Corrupt the payload behind the disk's back
+        unsafe {
+            let ptr = disk.mmap.as_ptr().add(second.payload_offset()) as *mut u8;
+            *ptr ^= 0xFF;
+        }
+        assert!(matches!(disk.read(second), Err(DiskError::Corrupted { offset, .. }) if offset == second.offset));
+    }
+
+    #[tokio::test]
+    async fn test_reservation_visible_only_after_commit() {
+        let disk = get_disk(None).await;
+
+        let first = disk.reserve(5).unwrap();
+        let second = disk.reserve(6).unwrap();
+        let abandoned = disk.reserve(4).unwrap();
+        let third = disk.reserve(5).unwrap();
+
+        // Nothing committed yet, so readers see nothing
+        assert_eq!(disk.iter().count(), 0);
+
+        // A committed record behind a pending one is still not visible
+        let second = second.commit(b"second").unwrap();
+        assert_eq!(disk.iter().count(), 0);
+        assert_eq!(disk.read(second).unwrap(), b"second");
+
+        first.commit(b"first").unwrap();
+        let payloads: Vec<&[u8]> = disk.iter().map(|r| r.unwrap().payload).collect();
+        assert_eq!(payloads, vec![b"first".as_slice(), b"second"]);
+
+        // Dropping without committing leaves a hole readers skip over
+        let abandoned_location = abandoned.location();
+        drop(abandoned);
+        assert_eq!(disk.read(abandoned_location), Err(DiskError::InvalidLocation));
+
+        assert!(matches!(
+            third.commit(b"too long"),
+            Err(DiskError::ReservationSizeMismatch { reserved: 5, actual: 8 })
+        ));
+        disk.append(b"last").unwrap();
+        assert_eq!(disk.items(), 3);
+
+        let mut iter = disk.iter();
+        let payloads: Vec<&[u8]> = iter.by_ref().map(|r| r.unwrap().payload).collect();
+        assert_eq!(payloads, vec![b"first".as_slice(), b"second", b"last"]);
+        assert_eq!(iter.holes(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_failed_reservation_does_not_consume_capacity() {
+        let disk = get_disk(Some(200)).await;
+        let available = 200 - disk.data_start() as u64;
+        assert_eq!(disk.remaining_capacity(), available);
+        assert_eq!(disk.used_bytes(), 0);
+
+        // Too big, and big enough to overflow the offset
+        assert_eq!(disk.append(&[0u8; 64]).unwrap_err(), DiskError::CapacityReached);
+        assert_eq!(disk.reserve_space(usize::MAX).unwrap_err(), DiskError::CapacityReached);
+        assert_eq!(disk.remaining_capacity(), available);
+        assert_eq!(disk.items(), 0);
+
+        // A record that fits is still accepted
+        let location = disk.append(b"fits").unwrap();
+        assert_eq!(location.offset, disk.data_start());
+        assert_eq!(disk.used_bytes(), location.size() as u64);
+        assert_eq!(disk.remaining_capacity(), available - location.size() as u64);
+
+        // Fill up exactly
+        let rest = disk.remaining_capacity() as usize - RECORD_HEADER_SIZE;
+        disk.append(&vec![1u8; rest]).unwrap();
+        assert_eq!(disk.remaining_capacity(), 0);
+        assert_eq!(disk.append(b"").unwrap_err(), DiskError::CapacityReached);
+    }
+
+    #[tokio::test]
+    async fn test_flush_does_not_touch_busy() {
+        let disk = get_disk(None).await;
+        disk.append(b"data").unwrap();
+        disk.flush().await.unwrap();
+        disk.flush().await.unwrap();
+        assert_eq!(disk.busy.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn test_partial_flushes() {
+        let disk = get_disk(None).await;
+        let first = disk.append(b"first").unwrap();
+        let generation = disk.header().generation;
+
+        // Only the pages of the record, the header is left alone
+        disk.flush_range(first.offset, first.size()).unwrap();
+        disk.flush_async().unwrap();
+        assert_eq!(disk.header().generation, generation);
+        assert_eq!(disk.header().record_count, 0);
+
+        assert_eq!(disk.flush_range(first.offset, 1024), Err(DiskError::InvalidLocation));
+        assert_eq!(disk.flush_range(usize::MAX, 2), Err(DiskError::InvalidLocation));
+
+        disk.blocking_flush().unwrap();
+        assert_eq!(disk.header().record_count, 1);
+    }
+
+    #[tokio::test]
+    async fn test_seal_waits_for_in_flight_writes() {
+        use std::sync::{Arc, Barrier};
+
+        let disk = Arc::new(get_disk(None).await);
+        let barrier = Arc::new(Barrier::new(2));
+
+        let writer_disk = Arc::clone(&disk);
+        let writer_barrier = Arc::clone(&barrier);
+        let writer = thread::spawn(move || {
+            let reservation = writer_disk.reserve(9).unwrap();
+            writer_barrier.wait();
+            thread::sleep(Duration::from_millis(100));
+            reservation.commit(b"in-flight").unwrap()
+        });
+
+        barrier.wait();
+        assert_eq!(disk.busy.load(Ordering::SeqCst), 1);
+        disk.seal().await.unwrap();
+
+        // Sealing returned only after the reservation was committed
+        assert_eq!(disk.busy.load(Ordering::SeqCst), 0);
+        let location = writer.join().unwrap();
+        assert_eq!(disk.read(location).unwrap(), b"in-flight");
+
+        assert!(disk.is_sealed());
+        assert!(matches!(disk.reserve(1), Err(DiskError::Locked)));
+        assert_eq!(disk.append(b"late"), Err(DiskError::Locked));
+    }
+
+    #[tokio::test]
+    async fn test_iter_never_sees_a_half_committed_record() {
+        use std::sync::Arc;
+        use std::thread;
+
+        let disk = Arc::new(get_disk(Some(64 * 1024)).await);
+
+        let writer_disk = Arc::clone(&disk);
+        let writer = thread::spawn(move || {
+            for i in 0..1000 {
+                writer_disk.append(format!("record-{}", i).as_bytes()).unwrap();
+            }
+        });
+
+        while !writer.is_finished() {
+            for record in disk.iter() {
+                record.unwrap();
+            }
+        }
+        writer.join().unwrap();
+
+        assert_eq!(disk.iter().count(), 1000);
+    }
+
+    #[tokio::test]
+    async fn test_seal_yields_to_writers_on_the_same_runtime() {
+        use std::sync::Arc;
+        use tokio::sync::oneshot;
+
+        let disk = Arc::new(get_disk(None).await);
+        let (reserved, on_reserved) = oneshot::channel();
+
+        // Current-thread runtime, the writer only gets to commit while `seal` waits
+        let writer_disk = Arc::clone(&disk);
+        let writer = tokio::spawn(async move {
+            let reservation = writer_disk.reserve(9).unwrap();
+            reserved.send(()).unwrap();
+            tokio::task::yield_now().await;
+            reservation.commit(b"in-flight").unwrap()
+        });
+
+        on_reserved.await.unwrap();
+        disk.seal().await.unwrap();
+
+        let location = writer.await.unwrap();
+        assert_eq!(disk.read(location).unwrap(), b"in-flight");
+        assert!(disk.is_sealed());
+    }
+
+    #[tokio::test]
+    async fn test_header_updated_in_place() {
+        let disk = get_disk(None).await;
+        disk.append(b"first").unwrap();
+        let last = disk.append(b"last").unwrap();
+        let abandoned = disk.reserve(3).unwrap();
+        drop(abandoned);
+
+        disk.flush().await.unwrap();
+        let header = disk.header();
+        assert_eq!(header.record_count, 2);
+        assert_eq!(header.last_checksum, RecordHeader::checksum(4, b"last"));
+        assert_eq!(header.sealed_at, 0);
+        assert!(!header.locked);
+
+        disk.seal().await.unwrap();
+        let (capacity, max_items, path) = (disk.capacity, disk.max_items, disk.path.clone());
+        drop(disk);
+
+        let reopened = Disk::open(DiskConf { capacity, max_items, ..disk_conf(path) }).await.unwrap();
+        let header = reopened.header();
+        assert!(header.locked);
+        assert_ne!(header.sealed_at, 0);
+        assert_eq!(reopened.recovery_report().last_record, Some(last));
+        assert_eq!(reopened.data_start(), TEST_HEADER_SIZE as usize);
+    }
+
+    #[tokio::test]
+    async fn test_header_counts_only_committed_records() {
+        let disk = get_disk(None).await;
+        let pending = disk.reserve(7).unwrap();
+        disk.append(b"first").unwrap();
+
+        disk.flush().await.unwrap();
+        assert_eq!(disk.items(), 2);
+        assert_eq!(disk.header().record_count, 1);
+
+        pending.commit(b"pending").unwrap();
+        disk.flush().await.unwrap();
+        assert_eq!(disk.header().record_count, 2);
+    }
+
+    #[tokio::test]
+    async fn test_torn_header_update_keeps_previous_header() {
+        let disk = get_disk(None).await;
+        let first = disk.append(b"first").unwrap();
+        disk.flush().await.unwrap();
+        let flushed = disk.header();
+
+        disk.seal().await.unwrap();
+        let sealed = disk.header();
+        assert_eq!(sealed.generation, flushed.generation + 2);
+        let (capacity, max_items, path) = (disk.capacity, disk.max_items, disk.path.clone());
+        drop(disk);
+
+        // The process died while writing the seal
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[sealed.slot_offset() + 20] ^= 0xFF;
+        std::fs::write(&path, &bytes).unwrap();
+
+        let reopened = Disk::open(DiskConf { capacity, max_items, ..disk_conf(path) }).await.unwrap();
+        let header = reopened.header();
+        assert_eq!(header.generation, sealed.generation - 1);
+        assert!(header.locked);
+        assert_eq!(header.sealed_at, 0);
+        assert_eq!(header.record_count, 1);
+        assert!(reopened.metadata().is_v1());
+        assert_eq!(reopened.read(first).unwrap(), b"first");
+
+        // The torn slot is the next one to be written
+        reopened.set_locked(false).unwrap();
+        assert_eq!(reopened.header().slot_offset(), sealed.slot_offset());
+    }
+
     async fn get_disk(capacity: Option<u64>) -> Disk {
         let fake_partial_folder_path = get_file(None, true);
 
         let conf = DiskConf {
             capacity: capacity.unwrap_or(1024),
-            max_items: 1,
-            disk_file_path: fake_partial_folder_path.clone(),
+            ..disk_conf(fake_partial_folder_path.clone())
         };
 
-        Disk::new(conf).await
+        Disk::open(conf).await.unwrap()
     }
 
     #[tokio::test]
@@ -360,7 +1451,7 @@ mod disk_tests {
         });
 
         // Lock the log before the threads start writing
-        disk.set_locked(true);
+        disk.set_locked(true).unwrap();
         barrier.wait(); // Let threads proceed
 
         let result1 = handle1.join().unwrap();
@@ -370,7 +1461,7 @@ mod disk_tests {
         assert!(matches!(result2, Err(DiskError::Locked)));
 
         // Unlock the log and retry
-        disk.set_locked(false);
+        disk.set_locked(false).unwrap();
         let entry = vec![9, 10, 11, 12];
         let reserve_space = disk.reserve_space(entry.len()).unwrap();
         assert!(disk.write(&entry, reserve_space).is_ok());
@@ -382,7 +1473,7 @@ mod disk_tests {
         use std::thread;
 
 
-        let disk = get_disk(Some(28)).await;
+        let disk = get_disk(Some(TEST_HEADER_SIZE + 9)).await;
 
         // Create a commit log with a small size to simulate running out of space
         let commit_log = Arc::new(disk); // Only 16 bytes available
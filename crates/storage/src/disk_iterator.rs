@@ -0,0 +1,219 @@
+use crate::cursor::Cursor;
+use crate::record::{DiskRecord, RecordHeader, RecordLocation, RECORD_HEADER_SIZE};
+use crate::DiskError;
+
+/// Walks the committed records of a disk, starting at the cursor's current position.
+///
+/// Iteration stops at the first region that does not hold a complete,
+/// committed record (unwritten space or a torn write). A record whose checksum
+/// does not match is yielded as `DiskError::Corrupted` and ends the iteration.
+/// Holes left by abandoned reservations are skipped.
+pub struct DiskIterator<'a> {
+    cursor: Cursor<'a>,
+    finished: bool,
+    holes: u64,
+    /// Pending reservations skipped, see `skip_pending`
+    pending: Option<Vec<RecordLocation>>,
+}
+
+impl<'a> DiskIterator<'a> {
+    /// `end` is the exclusive upper bound records are allowed to reach.
+    pub fn new(mut cursor: Cursor<'a>, end: usize) -> Self {
+        cursor.len = end.min(cursor.len);
+
+        Self {
+            cursor,
+            finished: false,
+            holes: 0,
+            pending: None,
+        }
+    }
+
+    /// Treat pending reservations as holes instead of stopping there.
+    /// Only right for a disk whose writer is gone, as in recovery.
+    pub fn skip_pending(mut self) -> Self {
+        self.pending = Some(vec![]);
+        self
+    }
+
+    /// Pending reservations skipped so far, counted as holes as well
+    pub fn pending(&self) -> &[RecordLocation] {
+        self.pending.as_deref().unwrap_or_default()
+    }
+
+    /// Number of holes skipped so far
+    pub fn holes(&self) -> u64 {
+        self.holes
+    }
+
+    /// Offset right after the last record yielded so far.
+    pub fn offset(&self) -> usize {
+        self.cursor.position
+    }
+
+    fn next_record(&mut self) -> Option<Result<DiskRecord<'a>, DiskError>> {
+        let mut offset = self.cursor.position;
+        let mut header = RecordHeader::load(self.cursor.peek(RECORD_HEADER_SIZE).ok()?);
+
+        while header.is_hole() || (header.is_pending() && self.pending.is_some()) {
+            let size = RECORD_HEADER_SIZE + header.length as usize;
+            self.cursor.peek(size).ok()?;
+
+            if let Some(pending) = self.pending.as_mut().filter(|_| header.is_pending()) {
+                pending.push(RecordLocation { offset, length: header.length as usize });
+            }
+
+            self.cursor.forward(size);
+            self.holes += 1;
+
+            offset = self.cursor.position;
+            header = RecordHeader::load(self.cursor.peek(RECORD_HEADER_SIZE).ok()?);
+        }
+
+        if !header.is_committed() {
+            return None;
+        }
+
+        let length = header.length as usize;
+        let record = self.cursor.peek(RECORD_HEADER_SIZE + length).ok()?;
+        let payload = &record[RECORD_HEADER_SIZE..];
+
+        if let Err(e) = header.verify(offset, payload) {
+            return Some(Err(e));
+        }
+
+        self.cursor.forward(RECORD_HEADER_SIZE + length);
+
+        Some(Ok(DiskRecord {
+            location: RecordLocation { offset, length },
+            payload,
+        }))
+    }
+}
+
+impl<'a> Iterator for DiskIterator<'a> {
+    type Item = Result<DiskRecord<'a>, DiskError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+
+        let record = self.next_record();
+        self.finished = !matches!(record, Some(Ok(_)));
+        record
+    }
+}
+
+#[cfg(test)]
+mod disk_iterator_tests {
+    use crate::cursor::Cursor;
+    use crate::disk_iterator::DiskIterator;
+    use crate::record::{RecordHeader, RecordLocation, RECORD_FLAG_COMMITTED, RECORD_FLAG_HOLE, RECORD_FLAG_PENDING, RECORD_HEADER_SIZE};
+    use crate::DiskError;
+
+    fn frame(payload: &[u8], flags: u8) -> Vec<u8> {
+        let mut bytes = RecordHeader::for_payload(payload, flags).unwrap().to_bytes().to_vec();
+        bytes.extend_from_slice(payload);
+        bytes
+    }
+
+    #[test]
+    pub fn test_iterates_committed_records() {
+        let mut bytes = frame(b"abc", RECORD_FLAG_COMMITTED);
+        bytes.extend(frame(b"", RECORD_FLAG_COMMITTED));
+        bytes.extend(frame(b"defg", RECORD_FLAG_COMMITTED));
+        bytes.extend([0u8; 16]);
+
+        let mut iter = DiskIterator::new(Cursor::new(&bytes), bytes.len());
+        let records: Vec<_> = iter.by_ref().map(|r| r.unwrap()).collect();
+
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0].payload, b"abc");
+        assert_eq!(records[0].location.offset, 0);
+        assert_eq!(records[1].payload, b"");
+        assert_eq!(records[2].payload, b"defg");
+        assert_eq!(records[2].location.offset, 2 * RECORD_HEADER_SIZE + 3);
+        assert_eq!(iter.offset(), 3 * RECORD_HEADER_SIZE + 7);
+    }
+
+    #[test]
+    pub fn test_stops_at_torn_record() {
+        let mut bytes = frame(b"abc", RECORD_FLAG_COMMITTED);
+        // Uncommitted header, payload may be partially written
+        bytes.extend(frame(b"xyz", 0));
+        bytes.extend(frame(b"def", RECORD_FLAG_COMMITTED));
+
+        let records: Vec<_> = DiskIterator::new(Cursor::new(&bytes), bytes.len()).collect();
+        assert_eq!(records.len(), 1);
+
+        // Header claims more bytes than are available
+        let mut bytes = frame(b"abc", RECORD_FLAG_COMMITTED);
+        bytes.extend(&RecordHeader::new(64, RECORD_FLAG_COMMITTED).to_bytes());
+        bytes.extend(b"short");
+
+        let mut iter = DiskIterator::new(Cursor::new(&bytes), bytes.len());
+        assert_eq!(iter.by_ref().count(), 1);
+        assert_eq!(iter.offset(), RECORD_HEADER_SIZE + 3);
+    }
+
+    #[test]
+    pub fn test_skips_holes() {
+        let mut bytes = frame(b"abc", RECORD_FLAG_COMMITTED);
+        bytes.extend(&RecordHeader::new(4, RECORD_FLAG_HOLE).to_bytes());
+        bytes.extend([0u8; 4]);
+        bytes.extend(frame(b"def", RECORD_FLAG_COMMITTED));
+
+        let mut iter = DiskIterator::new(Cursor::new(&bytes), bytes.len());
+        let payloads: Vec<_> = iter.by_ref().map(|r| r.unwrap().payload).collect();
+
+        assert_eq!(payloads, vec![b"abc".as_slice(), b"def"]);
+        assert_eq!(iter.holes(), 1);
+        assert_eq!(iter.offset(), bytes.len());
+    }
+
+    #[test]
+    pub fn test_pending_reservations() {
+        let mut bytes = frame(b"abc", RECORD_FLAG_COMMITTED);
+        bytes.extend(&RecordHeader::new(4, RECORD_FLAG_PENDING).to_bytes());
+        bytes.extend([0u8; 4]);
+        bytes.extend(frame(b"def", RECORD_FLAG_COMMITTED));
+
+        // Still being written, readers stop there
+        assert_eq!(DiskIterator::new(Cursor::new(&bytes), bytes.len()).count(), 1);
+
+        let mut iter = DiskIterator::new(Cursor::new(&bytes), bytes.len()).skip_pending();
+        let payloads: Vec<_> = iter.by_ref().map(|r| r.unwrap().payload).collect();
+        assert_eq!(payloads, vec![b"abc".as_slice(), b"def"]);
+        assert_eq!(iter.holes(), 1);
+        assert_eq!(iter.pending(), &[RecordLocation { offset: RECORD_HEADER_SIZE + 3, length: 4 }]);
+    }
+
+    #[test]
+    pub fn test_reports_checksum_mismatch() {
+        let mut bytes = frame(b"abc", RECORD_FLAG_COMMITTED);
+        bytes.extend(frame(b"defg", RECORD_FLAG_COMMITTED));
+        bytes.extend(frame(b"hij", RECORD_FLAG_COMMITTED));
+
+        // Flip a payload byte of the second record
+        let second = RECORD_HEADER_SIZE + 3;
+        bytes[second + RECORD_HEADER_SIZE] ^= 0xFF;
+        let expected = RecordHeader::from_bytes(&bytes[second..]).checksum;
+
+        let mut iter = DiskIterator::new(Cursor::new(&bytes), bytes.len());
+        assert!(iter.next().unwrap().is_ok());
+
+        let err = iter.next().unwrap().unwrap_err();
+        match err {
+            DiskError::Corrupted { offset, expected: e, actual } => {
+                assert_eq!(offset, second);
+                assert_eq!(e, expected);
+                assert_ne!(actual, expected);
+            }
+            _ => panic!("Unexpected error {:?}", err),
+        }
+
+        assert!(iter.next().is_none());
+        assert_eq!(iter.offset(), second);
+    }
+}
@@ -1,21 +1,260 @@
+use std::collections::BTreeMap;
 use enum_as_inner::EnumAsInner;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
 use crate::cursor::Cursor;
-use crate::U64_SIZE;
+use crate::{DiskError, U64_SIZE};
+
+/// Tag + Value Length
+pub const METADATA_FIELD_HEADER_SIZE: usize = 1 + 4;
+
+#[derive(Debug, Clone, EnumAsInner, Serialize, Deserialize, Error, PartialEq)]
+pub enum MetadataError {
+    #[error("Metadata payload is shorter than its layout requires")]
+    Truncated,
+    #[error("Unknown metadata version {version}")]
+    UnknownVersion {
+        version: u8,
+    },
+    #[error("Metadata field with tag {tag} holds an invalid value")]
+    InvalidField {
+        tag: u8,
+    },
+}
+
+impl From<MetadataError> for DiskError {
+    fn from(value: MetadataError) -> Self {
+        match value {
+            MetadataError::UnknownVersion { version } => DiskError::UnknownMetadataVersion { version },
+            MetadataError::Truncated | MetadataError::InvalidField { .. } => DiskError::InvalidMetadata,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompressionCodec {
+    None,
+    Lz4,
+    Zstd,
+    Snappy,
+}
+
+impl CompressionCodec {
+    pub fn get_le_identifier(&self) -> u8 {
+        match self {
+            CompressionCodec::None => 0u8,
+            CompressionCodec::Lz4 => 1u8,
+            CompressionCodec::Zstd => 2u8,
+            CompressionCodec::Snappy => 3u8,
+        }
+    }
+}
+
+impl TryFrom<u8> for CompressionCodec {
+    type Error = MetadataError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0u8 => Ok(CompressionCodec::None),
+            1u8 => Ok(CompressionCodec::Lz4),
+            2u8 => Ok(CompressionCodec::Zstd),
+            3u8 => Ok(CompressionCodec::Snappy),
+            _ => Err(MetadataError::InvalidField { tag: METADATA_TAG_COMPRESSION_CODEC }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumAlgorithm {
+    None,
+    Crc32c,
+}
+
+impl ChecksumAlgorithm {
+    pub fn get_le_identifier(&self) -> u8 {
+        match self {
+            ChecksumAlgorithm::None => 0u8,
+            ChecksumAlgorithm::Crc32c => 1u8,
+        }
+    }
+}
+
+impl TryFrom<u8> for ChecksumAlgorithm {
+    type Error = MetadataError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0u8 => Ok(ChecksumAlgorithm::None),
+            1u8 => Ok(ChecksumAlgorithm::Crc32c),
+            _ => Err(MetadataError::InvalidField { tag: METADATA_TAG_CHECKSUM_ALGORITHM }),
+        }
+    }
+}
+
+/// Fills the unused end of a metadata area, nothing is read past it
+pub const METADATA_TAG_PADDING: u8 = 0;
+pub const METADATA_TAG_NODE_ID: u8 = 1;
+pub const METADATA_TAG_DATASET_ID: u8 = 2;
+pub const METADATA_TAG_SCHEMA_VERSION: u8 = 3;
+pub const METADATA_TAG_COMPRESSION_CODEC: u8 = 4;
+pub const METADATA_TAG_CHECKSUM_ALGORITHM: u8 = 5;
+pub const METADATA_TAG_LABEL: u8 = 6;
 
 pub struct DiskMetadataV1 {
     pub created_at: u64
 }
 
+/// | Byte Range | Description              | Details                           |
+/// |------------|--------------------------|-----------------------------------|
+/// | 0          | Version (1 byte)         | 1                                 |
+/// | 1-9        | Created At (8 bytes)     | Seconds since UNIX epoch          |
+/// | 9...       | Fields (variable)        | Tag (1 byte) + Length (4 bytes) + Value |
+///
+/// Fields with a tag this version does not know are kept in `unknown` and
+/// written back untouched. A label is a field of its own, its value being the
+/// key length (4 bytes), the key and the value.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DiskMetadataV2 {
+    pub created_at: u64,
+    pub node_id: Option<String>,
+    pub dataset_id: Option<String>,
+    pub schema_version: Option<u32>,
+    pub compression_codec: Option<CompressionCodec>,
+    pub checksum_algorithm: Option<ChecksumAlgorithm>,
+    pub labels: BTreeMap<String, String>,
+    /// Tag and raw value of every field that was not understood
+    pub unknown: Vec<(u8, Vec<u8>)>,
+}
+
+impl DiskMetadataV2 {
+    fn write_field(vec: &mut Vec<u8>, tag: u8, value: &[u8]) {
+        vec.push(tag);
+        vec.extend_from_slice(&(value.len() as u32).to_le_bytes());
+        vec.extend_from_slice(value);
+    }
+
+    fn to_vec(&self) -> Vec<u8> {
+        let mut vec = vec![];
+        vec.extend_from_slice(&self.created_at.to_le_bytes());
+
+        if let Some(node_id) = &self.node_id {
+            Self::write_field(&mut vec, METADATA_TAG_NODE_ID, node_id.as_bytes());
+        }
+        if let Some(dataset_id) = &self.dataset_id {
+            Self::write_field(&mut vec, METADATA_TAG_DATASET_ID, dataset_id.as_bytes());
+        }
+        if let Some(schema_version) = self.schema_version {
+            Self::write_field(&mut vec, METADATA_TAG_SCHEMA_VERSION, &schema_version.to_le_bytes());
+        }
+        if let Some(codec) = self.compression_codec {
+            Self::write_field(&mut vec, METADATA_TAG_COMPRESSION_CODEC, &[codec.get_le_identifier()]);
+        }
+        if let Some(algorithm) = self.checksum_algorithm {
+            Self::write_field(&mut vec, METADATA_TAG_CHECKSUM_ALGORITHM, &[algorithm.get_le_identifier()]);
+        }
+        for (key, value) in &self.labels {
+            let mut label = Vec::with_capacity(4 + key.len() + value.len());
+            label.extend_from_slice(&(key.len() as u32).to_le_bytes());
+            label.extend_from_slice(key.as_bytes());
+            label.extend_from_slice(value.as_bytes());
+            Self::write_field(&mut vec, METADATA_TAG_LABEL, &label);
+        }
+        for (tag, value) in &self.unknown {
+            Self::write_field(&mut vec, *tag, value);
+        }
+
+        vec
+    }
+
+    fn read_string(tag: u8, value: &[u8]) -> Result<String, MetadataError> {
+        String::from_utf8(value.to_vec()).map_err(|_| MetadataError::InvalidField { tag })
+    }
+
+    fn from_cursor(cursor: &mut Cursor) -> Result<Self, MetadataError> {
+        let created_at_le_bytes = cursor.consume(U64_SIZE).map_err(|_| MetadataError::Truncated)?;
+        let mut metadata = DiskMetadataV2 {
+            created_at: u64::from_le_bytes(created_at_le_bytes.try_into().unwrap()),
+            ..Default::default()
+        };
+
+        while !cursor.is_eof() {
+            let tag = cursor.consume(1).map_err(|_| MetadataError::Truncated)?[0];
+            if tag == METADATA_TAG_PADDING {
+                break;
+            }
+
+            let length_le_bytes = cursor.consume(4).map_err(|_| MetadataError::Truncated)?;
+            let length = u32::from_le_bytes(length_le_bytes.try_into().unwrap()) as usize;
+            let value = cursor.consume(length).map_err(|_| MetadataError::Truncated)?;
+            let invalid = MetadataError::InvalidField { tag };
+
+            match tag {
+                METADATA_TAG_NODE_ID => metadata.node_id = Some(Self::read_string(tag, value)?),
+                METADATA_TAG_DATASET_ID => metadata.dataset_id = Some(Self::read_string(tag, value)?),
+                METADATA_TAG_SCHEMA_VERSION => {
+                    let value = value.try_into().map_err(|_| invalid)?;
+                    metadata.schema_version = Some(u32::from_le_bytes(value));
+                }
+                METADATA_TAG_COMPRESSION_CODEC => {
+                    let [codec] = value else { return Err(invalid) };
+                    metadata.compression_codec = Some(CompressionCodec::try_from(*codec)?);
+                }
+                METADATA_TAG_CHECKSUM_ALGORITHM => {
+                    let [algorithm] = value else { return Err(invalid) };
+                    metadata.checksum_algorithm = Some(ChecksumAlgorithm::try_from(*algorithm)?);
+                }
+                METADATA_TAG_LABEL => {
+                    let mut label = Cursor::new(value);
+                    let key_length = label.consume(4).map_err(|_| invalid.clone())?;
+                    let key_length = u32::from_le_bytes(key_length.try_into().unwrap()) as usize;
+                    let key = label.consume(key_length).map_err(|_| invalid)?;
+                    metadata.labels.insert(
+                        Self::read_string(tag, key)?,
+                        Self::read_string(tag, &value[label.position..])?,
+                    );
+                }
+                _ => metadata.unknown.push((tag, value.to_vec())),
+            }
+        }
+
+        Ok(metadata)
+    }
+}
+
 #[derive(EnumAsInner)]
 pub enum DiskMetadata {
-    V1(DiskMetadataV1)
+    V1(DiskMetadataV1),
+    V2(DiskMetadataV2),
 }
 
 impl DiskMetadata {
 
+    pub fn is_known_identifier(identifier: u8) -> bool {
+        matches!(identifier, 0u8 | 1u8)
+    }
+
+    pub fn created_at(&self) -> u64 {
+        match &self {
+            DiskMetadata::V1(data) => data.created_at,
+            DiskMetadata::V2(data) => data.created_at,
+        }
+    }
+
     pub fn get_le_identifier(&self) -> [u8; 1] {
         match &self {
-            DiskMetadata::V1(_) => [0u8]
+            DiskMetadata::V1(_) => [0u8],
+            DiskMetadata::V2(_) => [1u8],
+        }
+    }
+
+    /// Same metadata in the latest layout
+    pub fn upgrade(&self) -> DiskMetadataV2 {
+        match &self {
+            DiskMetadata::V1(data) => DiskMetadataV2 {
+                created_at: data.created_at,
+                ..Default::default()
+            },
+            DiskMetadata::V2(data) => data.clone(),
         }
     }
 
@@ -27,6 +266,10 @@ impl DiskMetadata {
                 vec.extend_from_slice(&self.get_le_identifier());
                 vec.extend_from_slice(&data.created_at.to_le_bytes());
             }
+            DiskMetadata::V2(data) => {
+                vec.extend_from_slice(&self.get_le_identifier());
+                vec.extend_from_slice(&data.to_vec());
+            }
         }
 
         vec
@@ -38,26 +281,88 @@ impl DiskMetadata {
                 // created_at
                 U64_SIZE
             }
+            DiskMetadata::V2(data) => data.to_vec().len(),
         }
     }
 
 }
 
 impl TryFrom<Vec<u8>> for DiskMetadata {
-    type Error = ();
+    type Error = MetadataError;
 
     fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
         let mut cursor = Cursor::new(&value);
-        let le_identifier = cursor.consume(1).unwrap();
-        match le_identifier.get(0).unwrap() {
+        let le_identifier = cursor.consume(1).map_err(|_| MetadataError::Truncated)?;
+        match le_identifier.first().unwrap() {
             0u8 => {
-                let created_at_le_bytes = cursor.consume(U64_SIZE).unwrap();
+                let created_at_le_bytes = cursor.consume(U64_SIZE).map_err(|_| MetadataError::Truncated)?;
                 let created_at = u64::from_le_bytes(created_at_le_bytes.try_into().unwrap());
                 Ok(DiskMetadata::V1(DiskMetadataV1 {
                     created_at,
                 }))
             }
-            _ => Err(())
+            1u8 => Ok(DiskMetadata::V2(DiskMetadataV2::from_cursor(&mut cursor)?)),
+            version => Err(MetadataError::UnknownVersion { version: *version })
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod disk_metadata_tests {
+    use crate::disk_metadata::{ChecksumAlgorithm, CompressionCodec, DiskMetadata, DiskMetadataV1, DiskMetadataV2, MetadataError};
+
+    fn v2() -> DiskMetadataV2 {
+        DiskMetadataV2 {
+            created_at: 42,
+            node_id: Some(String::from("node-1")),
+            dataset_id: Some(String::from("events")),
+            schema_version: Some(3),
+            compression_codec: Some(CompressionCodec::Zstd),
+            checksum_algorithm: Some(ChecksumAlgorithm::Crc32c),
+            labels: [(String::from("env"), String::from("prod"))].into(),
+            unknown: vec![],
+        }
+    }
+
+    #[test]
+    pub fn test_v2_round_trip() {
+        let bytes = DiskMetadata::V2(v2()).to_vec();
+        let decoded = DiskMetadata::try_from(bytes).unwrap();
+        assert_eq!(decoded.as_v2(), Some(&v2()));
+
+        // An empty V2 takes as much room as a V1
+        let upgraded = DiskMetadata::V1(DiskMetadataV1 { created_at: 42 }).upgrade();
+        assert_eq!(upgraded.created_at, 42);
+        assert_eq!(DiskMetadata::V2(upgraded).to_vec().len(), 9);
+    }
+
+    #[test]
+    pub fn test_v2_keeps_unknown_fields_and_skips_padding() {
+        let mut bytes = DiskMetadata::V2(v2()).to_vec();
+        bytes.extend_from_slice(&[200, 2, 0, 0, 0, 7, 7]);
+        bytes.extend_from_slice(&[0, 0, 0, 0]);
+
+        let decoded = DiskMetadata::try_from(bytes).unwrap().into_v2().ok().unwrap();
+        assert_eq!(decoded.unknown, vec![(200, vec![7, 7])]);
+        assert_eq!(DiskMetadataV2 { unknown: vec![], ..decoded.clone() }, v2());
+
+        let again = DiskMetadata::try_from(DiskMetadata::V2(decoded.clone()).to_vec()).unwrap();
+        assert_eq!(again.as_v2(), Some(&decoded));
+    }
+
+    #[test]
+    pub fn test_rejects_malformed() {
+        assert_eq!(DiskMetadata::try_from(vec![]).err(), Some(MetadataError::Truncated));
+        assert_eq!(DiskMetadata::try_from(vec![0, 1, 2]).err(), Some(MetadataError::Truncated));
+        assert_eq!(DiskMetadata::try_from(vec![9]).err(), Some(MetadataError::UnknownVersion { version: 9 }));
+
+        let mut bytes = DiskMetadata::V2(DiskMetadataV2::default()).to_vec();
+        bytes.extend_from_slice(&[4, 1, 0, 0, 0, 99]);
+        assert_eq!(DiskMetadata::try_from(bytes).err(), Some(MetadataError::InvalidField { tag: 4 }));
+
+        // Field longer than what is left
+        let mut bytes = DiskMetadata::V2(DiskMetadataV2::default()).to_vec();
+        bytes.extend_from_slice(&[1, 10, 0, 0, 0, b'a']);
+        assert_eq!(DiskMetadata::try_from(bytes).err(), Some(MetadataError::Truncated));
+    }
+}
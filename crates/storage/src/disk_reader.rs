@@ -0,0 +1,166 @@
+use std::path::{Path, PathBuf};
+use memmap2::Mmap;
+use tokio::fs::OpenOptions;
+use crate::cursor::Cursor;
+use crate::disk::{Disk, RecoveryReport};
+use crate::disk_iterator::DiskIterator;
+use crate::disk_metadata::DiskMetadata;
+use crate::header::DiskHeader;
+use crate::record::RecordLocation;
+use crate::DiskError;
+
+/// Read-only view of a disk file, mapped with `Mmap`.
+///
+/// The file is opened without write access and nothing here can modify it,
+/// so any number of readers, in any number of processes, can map the same
+/// disk. Readers only see the records that were committed when they opened it.
+pub struct DiskReader {
+    pub path: PathBuf,
+    pub capacity: u64,
+    mmap: Mmap,
+    header: DiskHeader,
+    metadata: DiskMetadata,
+    recovery: RecoveryReport,
+}
+
+impl DiskReader {
+    pub async fn open<P: AsRef<Path>>(path: P) -> Result<Self, DiskError> {
+        let path = path.as_ref();
+
+        let file = OpenOptions::new()
+            .read(true)
+            .open(path)
+            .await
+            .map_err(|e| DiskError::io(path, e))?;
+
+        let mmap = unsafe { Mmap::map(&file).map_err(|e| DiskError::io(path, e))? };
+
+        // Nothing to read in a file that was never initialized
+        let (header, metadata) = Disk::parse_header(&mmap)?.ok_or(DiskError::NotADiskFile)?;
+
+        let mut cursor = Cursor::mmap(&mmap);
+        cursor.len = header.capacity as usize;
+        let (recovery, _, _) = Disk::recover(cursor, header.header_size as usize, header.record_count);
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            capacity: header.capacity,
+            mmap,
+            header,
+            metadata,
+            recovery,
+        })
+    }
+
+    pub fn recovery_report(&self) -> &RecoveryReport {
+        &self.recovery
+    }
+
+    /// Header fields as they were when the reader was opened
+    pub fn header(&self) -> &DiskHeader {
+        &self.header
+    }
+
+    pub fn metadata(&self) -> &DiskMetadata {
+        &self.metadata
+    }
+
+    pub fn data_start(&self) -> usize {
+        self.header.header_size as usize
+    }
+
+    /// Number of committed records visible to this reader
+    pub fn items(&self) -> u64 {
+        self.recovery.records
+    }
+
+    pub fn is_sealed(&self) -> bool {
+        self.header.locked
+    }
+
+    /// Read the payload of the record at `location` without copying it
+    pub fn read(&self, location: RecordLocation) -> Result<&[u8], DiskError> {
+        if location.offset < self.data_start() {
+            return Err(DiskError::InvalidLocation);
+        }
+
+        let mut cursor = Cursor::mmap(&self.mmap);
+        cursor.len = self.recovery.write_offset;
+
+        location.read(cursor)
+    }
+
+    /// Same as `read`, but hands back an owned copy of the payload
+    pub fn read_owned(&self, location: RecordLocation) -> Result<Vec<u8>, DiskError> {
+        self.read(location).map(|payload| payload.to_vec())
+    }
+
+    /// Iterate over every record visible to this reader
+    pub fn iter(&self) -> DiskIterator<'_> {
+        let cursor = Cursor::mmap(&self.mmap).set_starting_pos(self.data_start());
+        DiskIterator::new(cursor, self.recovery.write_offset).skip_pending()
+    }
+}
+
+#[cfg(test)]
+mod disk_reader_tests {
+    use crate::disk::Disk;
+    use crate::utils::test_utils::{disk_conf, get_file};
+    use crate::DiskError;
+
+    #[tokio::test]
+    pub async fn test_reads_sealed_disk() {
+        let path = get_file(None, true);
+        let disk = Disk::open(disk_conf(path.clone())).await.unwrap();
+
+        let first = disk.append(b"first").unwrap();
+        let second = disk.append(b"second").unwrap();
+        disk.seal().await.unwrap();
+
+        // Several readers side by side with the writer
+        let reader = Disk::open_read_only(&path).await.unwrap();
+        let other = Disk::open_read_only(&path).await.unwrap();
+
+        assert!(reader.is_sealed());
+        assert_eq!(reader.items(), 2);
+        assert_eq!(reader.capacity, 1024);
+        assert_eq!(reader.metadata().created_at(), disk.metadata().created_at());
+        assert_eq!(reader.read(first).unwrap(), b"first");
+        assert_eq!(other.read_owned(second).unwrap(), b"second".to_vec());
+
+        let payloads: Vec<&[u8]> = reader.iter().map(|r| r.unwrap().payload).collect();
+        assert_eq!(payloads, vec![b"first".as_slice(), b"second"]);
+    }
+
+    #[tokio::test]
+    pub async fn test_only_sees_what_was_committed_when_opened() {
+        let path = get_file(None, true);
+        let disk = Disk::open(disk_conf(path.clone())).await.unwrap();
+        disk.append(b"before").unwrap();
+
+        let reader = Disk::open_read_only(&path).await.unwrap();
+        let after = disk.append(b"after").unwrap();
+
+        assert!(!reader.is_sealed());
+        assert_eq!(reader.iter().count(), 1);
+        assert_eq!(reader.read(after), Err(DiskError::InvalidLocation));
+    }
+
+    #[tokio::test]
+    pub async fn test_rejects_files_that_are_not_disks() {
+        let path = get_file(None, true);
+        std::fs::write(&path, b"").unwrap();
+        assert_eq!(Disk::open_read_only(&path).await.err(), Some(DiskError::NotADiskFile));
+
+        std::fs::write(&path, b"definitely not a disk").unwrap();
+        assert_eq!(Disk::open_read_only(&path).await.err(), Some(DiskError::NotADiskFile));
+
+        // Never created
+        let missing = get_file(None, true);
+        assert!(matches!(
+            Disk::open_read_only(&missing).await,
+            Err(DiskError::Io { kind: std::io::ErrorKind::NotFound, .. })
+        ));
+        assert!(!missing.exists());
+    }
+}
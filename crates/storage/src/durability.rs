@@ -0,0 +1,328 @@
+use std::ops::Range;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+use memmap2::MmapMut;
+use tokio::sync::{oneshot, Notify};
+use tokio::task::JoinHandle;
+use crate::record::RecordLocation;
+use crate::DiskError;
+
+/// When the records appended to a disk are written back to the file.
+///
+/// Whatever the mode, `Disk::wait_durable` only returns once the record it
+/// waits for was flushed, records committed around the same time are
+/// flushed together.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Durability {
+    /// Only when asked to, by `Disk::flush` or by waiting for a record
+    #[default]
+    None,
+    /// Every interval
+    Periodic(Duration),
+    /// Once that many records were committed since the last flush, or as
+    /// soon as one of them is waited for
+    EveryN(u64),
+    /// As soon as records are committed
+    Always,
+}
+
+type Waiter = oneshot::Sender<Result<(), DiskError>>;
+
+/// Records committed since the previous batch, and who waits for them
+#[derive(Default)]
+struct Batch {
+    /// Span of the records, flushed all at once
+    range: Option<Range<usize>>,
+    records: u64,
+    waiters: Vec<Waiter>,
+}
+
+impl Batch {
+    fn covers(&self, location: RecordLocation) -> bool {
+        self.range.as_ref().is_some_and(|range| range.contains(&location.offset))
+    }
+
+    fn extend(&mut self, range: Range<usize>) {
+        self.range = Some(match self.range.take() {
+            Some(current) => current.start.min(range.start)..current.end.max(range.end),
+            None => range,
+        });
+    }
+}
+
+#[derive(Default)]
+struct Pending {
+    next: Batch,
+    /// Batch being flushed right now
+    in_flight: Option<Batch>,
+    /// Everything before it was written back. Flushes start from there, so
+    /// the reservation headers in front of a record are durable along with it.
+    flushed_to: usize,
+}
+
+impl Pending {
+    /// Put the next batch in flight and return the range to write back for it,
+    /// all of the `len` bytes of the mapping if `everything`
+    fn start_flush(&mut self, everything: bool, len: usize) -> Option<Range<usize>> {
+        let batch = std::mem::take(&mut self.next);
+        let range = match (everything, &batch.range) {
+            (true, _) => Some(0..len),
+            (false, Some(range)) => Some(self.flushed_to.min(range.start)..range.end),
+            (false, None) => None,
+        };
+        self.in_flight = Some(batch);
+
+        range
+    }
+}
+
+struct Shared {
+    durability: Durability,
+    pending: Mutex<Pending>,
+    /// Wakes the flusher up when a batch is due
+    due: Notify,
+    /// Only one batch is flushed at a time
+    flushing: tokio::sync::Mutex<()>,
+}
+
+impl Shared {
+    /// Flush the records committed so far, or all of `mmap` if `everything`,
+    /// then acknowledge whoever waits for them
+    async fn flush(&self, mmap: &Arc<MmapMut>, everything: bool) -> Result<(), DiskError> {
+        let _flushing = self.flushing.lock().await;
+
+        let Some(range) = self.pending.lock().unwrap().start_flush(everything, mmap.len()) else {
+            return Ok(());
+        };
+
+        let end = range.end;
+        let mmap = mmap.clone();
+        let result = tokio::task::spawn_blocking(move || mmap.flush_range(range.start, range.len()))
+            .await
+            .map_err(|_| DiskError::InvalidFlushing)
+            .and_then(|flushed| flushed.map_err(|_| DiskError::InvalidFlushing));
+
+        let batch = {
+            let mut pending = self.pending.lock().unwrap();
+            if result.is_ok() {
+                pending.flushed_to = pending.flushed_to.max(end);
+            }
+            pending.in_flight.take()
+        };
+        for waiter in batch.into_iter().flat_map(|batch| batch.waiters) {
+            let _ = waiter.send(result.clone());
+        }
+
+        result
+    }
+}
+
+/// Group commit for a disk: a flusher task writes back the records that were
+/// committed, following the `Durability` of the disk, and acknowledges the
+/// ones that are waited for once the range covering them is on disk.
+pub(crate) struct GroupCommit {
+    shared: Arc<Shared>,
+    flusher: JoinHandle<()>,
+}
+
+impl GroupCommit {
+    pub fn spawn(mmap: Arc<MmapMut>, durability: Durability) -> Self {
+        let shared = Arc::new(Shared {
+            durability,
+            pending: Mutex::new(Pending::default()),
+            due: Notify::new(),
+            flushing: tokio::sync::Mutex::new(()),
+        });
+
+        Self {
+            flusher: tokio::spawn(Self::run(shared.clone(), Arc::downgrade(&mmap))),
+            shared,
+        }
+    }
+
+    /// Keep flushing with a new mapping of the same file
+    pub fn restart(&mut self, mmap: Arc<MmapMut>) {
+        self.flusher.abort();
+        self.flusher = tokio::spawn(Self::run(self.shared.clone(), Arc::downgrade(&mmap)));
+    }
+
+    /// The mapping is only borrowed while flushing, it keeps the file and
+    /// its writer lock alive and must go away along with the disk.
+    async fn run(shared: Arc<Shared>, mmap: Weak<MmapMut>) {
+        // An interval can't be empty
+        let mut ticks = match shared.durability {
+            Durability::Periodic(every) => Some(tokio::time::interval(every.max(Duration::from_millis(1)))),
+            _ => None,
+        };
+
+        loop {
+            match ticks.as_mut() {
+                Some(ticks) => tokio::select! {
+                    _ = ticks.tick() => {}
+                    _ = shared.due.notified() => {}
+                },
+                None => shared.due.notified().await,
+            }
+
+            let Some(mmap) = mmap.upgrade() else {
+                return;
+            };
+
+            // Failures go to the waiters, the next batch tries again
+            let _ = shared.flush(&mmap, false).await;
+        }
+    }
+
+    /// Add the record at `location`, just committed, to the next batch
+    pub fn committed(&self, location: RecordLocation) {
+        let mut pending = self.shared.pending.lock().unwrap();
+        let batch = &mut pending.next;
+
+        batch.extend(location.offset..location.offset + location.size());
+        batch.records += 1;
+
+        let due = match self.shared.durability {
+            Durability::Always => true,
+            Durability::EveryN(records) => batch.records >= records,
+            Durability::None | Durability::Periodic(_) => false,
+        };
+
+        if due {
+            self.shared.due.notify_one();
+        }
+    }
+
+    /// Add the header just written for the reservation at `location`, pending
+    /// or abandoned, to the next batch
+    pub fn reserved(&self, location: RecordLocation) {
+        self.shared.pending.lock().unwrap().next.extend(location.offset..location.payload_offset());
+    }
+
+    /// Wait until the committed record at `location` was flushed
+    pub async fn wait(&self, location: RecordLocation) -> Result<(), DiskError> {
+        let (waiter, flushed) = oneshot::channel();
+
+        {
+            let mut pending = self.shared.pending.lock().unwrap();
+
+            if pending.next.covers(location) {
+                pending.next.waiters.push(waiter);
+
+                // Nothing else would ever flush it, or not before a batch fills up
+                if matches!(self.shared.durability, Durability::None | Durability::EveryN(_)) {
+                    self.shared.due.notify_one();
+                }
+            } else if let Some(in_flight) = pending.in_flight.as_mut().filter(|batch| batch.covers(location)) {
+                in_flight.waiters.push(waiter);
+            } else {
+                return Ok(());
+            }
+        }
+
+        flushed.await.map_err(|_| DiskError::InvalidFlushing)?
+    }
+
+    /// Flush all of `mmap`, acknowledging every record committed so far
+    pub async fn flush(&self, mmap: &Arc<MmapMut>) -> Result<(), DiskError> {
+        self.shared.flush(mmap, true).await
+    }
+}
+
+impl Drop for GroupCommit {
+    fn drop(&mut self) {
+        self.flusher.abort();
+    }
+}
+
+#[cfg(test)]
+mod durability_tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+    use crate::disk::{Disk, DiskConf};
+    use crate::durability::{Durability, Pending};
+    use crate::utils::test_utils::{disk_conf, get_file};
+
+    async fn disk(durability: Durability) -> Arc<Disk> {
+        Arc::new(Disk::open(DiskConf {
+            capacity: 4096,
+            durability,
+            ..disk_conf(get_file(None, true))
+        }).await.unwrap())
+    }
+
+    #[tokio::test]
+    pub async fn test_concurrent_appends_are_acknowledged() {
+        for durability in [
+            Durability::None,
+            Durability::Periodic(Duration::from_millis(5)),
+            Durability::EveryN(1),
+            Durability::Always,
+        ] {
+            let disk = disk(durability).await;
+
+            let handles: Vec<_> = (0..8)
+                .map(|i| {
+                    let disk = disk.clone();
+                    tokio::spawn(async move { disk.append_durable(format!("{}", i).as_bytes()).await })
+                })
+                .collect();
+
+            for handle in handles {
+                let location = handle.await.unwrap().unwrap();
+                disk.wait_durable(location).await.unwrap();
+            }
+
+            assert_eq!(disk.items(), 8);
+        }
+    }
+
+    #[tokio::test]
+    pub async fn test_every_n_flushes_a_partial_batch_when_waited_for() {
+        let disk = disk(Durability::EveryN(2)).await;
+        let first = disk.append(b"first").unwrap();
+        disk.wait_durable(first).await.unwrap();
+
+        // The last records of a run never fill a batch
+        let second = disk.append(b"second").unwrap();
+        let third = disk.append(b"third").unwrap();
+        let last = disk.append_durable(b"last").await.unwrap();
+        disk.wait_durable(second).await.unwrap();
+        disk.wait_durable(third).await.unwrap();
+        disk.wait_durable(last).await.unwrap();
+    }
+
+    #[test]
+    pub fn test_flush_starts_at_the_watermark() {
+        let mut pending = Pending { flushed_to: 100, ..Pending::default() };
+
+        // A reservation pending at 150 is flushed along with a record after it
+        pending.next.extend(300..320);
+        assert_eq!(pending.start_flush(false, 4096), Some(100..320));
+
+        // A header rewritten behind the watermark comes first
+        pending.flushed_to = 320;
+        pending.next.extend(150..159);
+        pending.next.extend(400..420);
+        assert_eq!(pending.start_flush(false, 4096), Some(150..420));
+
+        assert_eq!(pending.start_flush(false, 4096), None);
+        assert_eq!(pending.start_flush(true, 4096), Some(0..4096));
+    }
+
+    #[tokio::test]
+    pub async fn test_flush_acknowledges_waiters() {
+        let disk = disk(Durability::Periodic(Duration::from_secs(3600))).await;
+        let location = disk.append(b"record").unwrap();
+
+        // The flusher won't get to it for a while, an explicit flush covers it
+        let (waited, flushed) = tokio::join!(disk.wait_durable(location), async {
+            tokio::task::yield_now().await;
+            disk.flush().await
+        });
+        waited.unwrap();
+        flushed.unwrap();
+
+        // Already on disk
+        disk.wait_durable(location).await.unwrap();
+    }
+}
@@ -0,0 +1,349 @@
+use std::path::Path;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use crate::{DiskError, U64_SIZE};
+
+/// Identifies a shugart disk file
+pub const DISK_MAGIC: [u8; 8] = *b"SHUGART\0";
+
+/// Layout version of the fixed header fields
+pub const DISK_FORMAT_VERSION: u16 = 3;
+
+/// Size of the reserved header region unless configured otherwise, one page
+pub const DEFAULT_HEADER_SIZE: u64 = 4096;
+
+/// Magic + Format Version + Header Size
+pub const DISK_HEADER_PREFIX_SIZE: usize = 8 + 2 + 8;
+
+/// Generation + Capacity + Locked flag + Record Count + Sealed At + Last Checksum + Metadata Length + Checksum
+pub const HEADER_SLOT_FIXED_SIZE: usize = 8 + 8 + 1 + 8 + 8 + 4 + 8 + 4;
+
+/// Prefix + the fixed fields of both slots, what is left of the region goes to metadata
+pub const DISK_HEADER_FIXED_SIZE: usize = DISK_HEADER_PREFIX_SIZE + 2 * HEADER_SLOT_FIXED_SIZE;
+
+/// Number of slots the header alternates between
+pub const HEADER_SLOTS: u64 = 2;
+
+const SLOT_CAPACITY_OFFSET: usize = 8;
+const SLOT_LOCKED_OFFSET: usize = 16;
+const SLOT_RECORD_COUNT_OFFSET: usize = 17;
+const SLOT_SEALED_AT_OFFSET: usize = 25;
+const SLOT_LAST_CHECKSUM_OFFSET: usize = 33;
+const SLOT_METADATA_LENGTH_OFFSET: usize = 37;
+const SLOT_CHECKSUM_OFFSET: usize = 45;
+
+/// Fixed fields at the start of every disk file.
+///
+/// The header takes a reserved region of `header_size` bytes, so the
+/// metadata payload and the fields below can be updated in place without
+/// ever moving data. Records start right after it.
+///
+/// Past a prefix that never changes, the region is split in two slots of
+/// `slot_size` bytes. Every update bumps the generation and goes to the slot
+/// the current header is not in (`generation % 2`), so a crash halfway
+/// through an update leaves the previous header intact. On open, the slot
+/// with a valid checksum and the highest generation wins.
+///
+/// | Byte Range | Description                | Details                                 |
+/// |------------|----------------------------|-----------------------------------------|
+/// | 0-8        | Magic (8 bytes)            | `DISK_MAGIC`                            |
+/// | 8-10       | Format Version (2 bytes)   | `DISK_FORMAT_VERSION`                   |
+/// | 10-18      | Header Size (8 bytes)      | Size of the reserved region, data start |
+/// | 18...      | Slot 0 (`slot_size` bytes) | Even generations                        |
+/// | ...        | Slot 1 (`slot_size` bytes) | Odd generations                         |
+///
+/// Every slot is laid out as:
+///
+/// | Byte Range | Description                | Details                                 |
+/// |------------|----------------------------|-----------------------------------------|
+/// | 0-8        | Generation (8 bytes)       | Bumped on every update                  |
+/// | 8-16       | Capacity (8 bytes)         | Size of the file in bytes               |
+/// | 16         | Locked flag (1 byte)       | 0 = Unlocked, 1 = Locked                |
+/// | 17-25      | Record Count (8 bytes)     | Committed records as of the last flush  |
+/// | 25-33      | Sealed At (8 bytes)        | Seconds since UNIX epoch, 0 = not sealed |
+/// | 33-37      | Last Checksum (4 bytes)    | CRC32C of the last record, as of the last flush |
+/// | 37-45      | Metadata Length (8 bytes)  | Length of the metadata payload in bytes |
+/// | 45-49      | Checksum (4 bytes)         | CRC32C of the fields above and the metadata |
+/// | 49...      | Metadata payload (variable) | Zero-padded up to `slot_size`          |
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DiskHeader {
+    pub format_version: u16,
+    pub header_size: u64,
+    pub generation: u64,
+    pub capacity: u64,
+    pub locked: bool,
+    pub record_count: u64,
+    pub sealed_at: u64,
+    pub last_checksum: u32,
+    pub metadata_length: u64,
+}
+
+impl DiskHeader {
+    pub fn new(header_size: u64, capacity: u64, metadata_length: u64) -> Self {
+        Self {
+            format_version: DISK_FORMAT_VERSION,
+            header_size,
+            generation: 0,
+            capacity,
+            locked: false,
+            record_count: 0,
+            sealed_at: 0,
+            last_checksum: 0,
+            metadata_length,
+        }
+    }
+
+    /// Whether `bytes` start with the disk magic
+    pub fn has_magic(bytes: &[u8]) -> bool {
+        bytes.starts_with(&DISK_MAGIC)
+    }
+
+    /// Look at the start of a file before touching it.
+    ///
+    /// Returns `None` when `bytes` are all zero, for a file that is empty or
+    /// zeroed and still free to be initialized. Fails for anything that is not
+    /// a disk this version can open.
+    pub fn probe(bytes: &[u8]) -> Result<Option<Self>, DiskError> {
+        if bytes.iter().all(|b| *b == 0) {
+            return Ok(None);
+        }
+
+        Self::from_bytes(bytes).map(Some)
+    }
+
+    /// Read the header region from the start of `reader`, or as much of it as there is.
+    /// Without a header, that is the `header_size` bytes a new one would take.
+    pub async fn read_region<R: AsyncRead + Unpin>(reader: R, header_size: u64) -> std::io::Result<Vec<u8>> {
+        let mut reader = reader.take(DISK_HEADER_PREFIX_SIZE as u64);
+        let mut bytes = Vec::with_capacity(DISK_HEADER_PREFIX_SIZE);
+        reader.read_to_end(&mut bytes).await?;
+
+        let header_size = if bytes.len() == DISK_HEADER_PREFIX_SIZE && Self::has_magic(&bytes) {
+            u64::from_le_bytes(bytes[10..DISK_HEADER_PREFIX_SIZE].try_into().unwrap())
+        } else {
+            header_size
+        };
+
+        reader.set_limit(header_size.saturating_sub(bytes.len() as u64));
+        reader.read_to_end(&mut bytes).await?;
+
+        Ok(bytes)
+    }
+
+    /// Probe the header of the file at `path`, `None` if there is no such file.
+    /// See `read_region` for `header_size`.
+    pub async fn load(path: &Path, header_size: u64) -> Result<Option<Self>, DiskError> {
+        let file = match tokio::fs::File::open(path).await {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(DiskError::io(path, e)),
+        };
+
+        let bytes = Self::read_region(file, header_size).await.map_err(|e| DiskError::io(path, e))?;
+
+        Self::probe(&bytes)
+    }
+
+    /// Size of each of the two slots
+    pub fn slot_size(&self) -> usize {
+        (self.header_size as usize).saturating_sub(DISK_HEADER_PREFIX_SIZE) / HEADER_SLOTS as usize
+    }
+
+    /// Offset of the slot this header goes to
+    pub fn slot_offset(&self) -> usize {
+        DISK_HEADER_PREFIX_SIZE + (self.generation % HEADER_SLOTS) as usize * self.slot_size()
+    }
+
+    /// Offset of the metadata payload of this header
+    pub fn metadata_offset(&self) -> usize {
+        self.slot_offset() + HEADER_SLOT_FIXED_SIZE
+    }
+
+    /// Room left for the metadata payload
+    pub fn metadata_capacity(&self) -> usize {
+        self.slot_size().saturating_sub(HEADER_SLOT_FIXED_SIZE)
+    }
+
+    /// Same header, one generation later so it lands in the other slot
+    pub fn next(&self) -> Self {
+        Self {
+            generation: self.generation + 1,
+            ..*self
+        }
+    }
+
+    /// Fields that never change once the file is initialized
+    pub fn prefix_bytes(&self) -> [u8; DISK_HEADER_PREFIX_SIZE] {
+        let mut bytes = [0u8; DISK_HEADER_PREFIX_SIZE];
+
+        bytes[0..8].copy_from_slice(&DISK_MAGIC);
+        bytes[8..10].copy_from_slice(&self.format_version.to_le_bytes());
+        bytes[10..DISK_HEADER_PREFIX_SIZE].copy_from_slice(&self.header_size.to_le_bytes());
+
+        bytes
+    }
+
+    /// The whole slot of this header, `metadata` included and zero-padded to `slot_size`
+    pub fn slot_bytes(&self, metadata: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![0u8; self.slot_size().max(HEADER_SLOT_FIXED_SIZE + metadata.len())];
+
+        bytes[0..SLOT_CAPACITY_OFFSET].copy_from_slice(&self.generation.to_le_bytes());
+        bytes[SLOT_CAPACITY_OFFSET..SLOT_LOCKED_OFFSET].copy_from_slice(&self.capacity.to_le_bytes());
+        bytes[SLOT_LOCKED_OFFSET] = self.locked as u8;
+        bytes[SLOT_RECORD_COUNT_OFFSET..SLOT_SEALED_AT_OFFSET].copy_from_slice(&self.record_count.to_le_bytes());
+        bytes[SLOT_SEALED_AT_OFFSET..SLOT_LAST_CHECKSUM_OFFSET].copy_from_slice(&self.sealed_at.to_le_bytes());
+        bytes[SLOT_LAST_CHECKSUM_OFFSET..SLOT_METADATA_LENGTH_OFFSET].copy_from_slice(&self.last_checksum.to_le_bytes());
+        bytes[SLOT_METADATA_LENGTH_OFFSET..SLOT_CHECKSUM_OFFSET].copy_from_slice(&self.metadata_length.to_le_bytes());
+        bytes[HEADER_SLOT_FIXED_SIZE..HEADER_SLOT_FIXED_SIZE + metadata.len()].copy_from_slice(metadata);
+
+        let checksum = Self::slot_checksum(&bytes[..SLOT_CHECKSUM_OFFSET], metadata);
+        bytes[SLOT_CHECKSUM_OFFSET..HEADER_SLOT_FIXED_SIZE].copy_from_slice(&checksum.to_le_bytes());
+
+        bytes
+    }
+
+    /// The whole header region with this header in its slot and the other slot left empty
+    pub fn to_bytes(&self, metadata: &[u8]) -> Vec<u8> {
+        let slot = self.slot_bytes(metadata);
+        let mut bytes = vec![0u8; (self.header_size as usize).max(self.slot_offset() + slot.len())];
+
+        bytes[..DISK_HEADER_PREFIX_SIZE].copy_from_slice(&self.prefix_bytes());
+        bytes[self.slot_offset()..self.slot_offset() + slot.len()].copy_from_slice(&slot);
+
+        bytes
+    }
+
+    /// Parse the header region, picking the latest slot that was fully written
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, DiskError> {
+        if bytes.len() < DISK_HEADER_PREFIX_SIZE || !Self::has_magic(bytes) {
+            return Err(DiskError::NotADiskFile);
+        }
+
+        let format_version = u16::from_le_bytes(bytes[8..10].try_into().unwrap());
+        if format_version != DISK_FORMAT_VERSION {
+            return Err(DiskError::UnsupportedVersion { version: format_version });
+        }
+
+        let header_size = u64::from_le_bytes(bytes[10..DISK_HEADER_PREFIX_SIZE].try_into().unwrap());
+
+        (0..HEADER_SLOTS)
+            .filter_map(|slot| Self::read_slot(bytes, header_size, slot))
+            .max_by_key(|header| header.generation)
+            .ok_or(DiskError::CorruptedHeader)
+    }
+
+    /// Header stored in `slot`, `None` if the slot is empty or was torn
+    fn read_slot(bytes: &[u8], header_size: u64, slot: u64) -> Option<Self> {
+        let empty = Self {
+            generation: slot,
+            ..Self::new(header_size, 0, 0)
+        };
+
+        let start = empty.slot_offset();
+        let fields = bytes.get(start..start + HEADER_SLOT_FIXED_SIZE)?;
+        let read_u64 = |at: usize| u64::from_le_bytes(fields[at..at + U64_SIZE].try_into().unwrap());
+
+        let header = Self {
+            generation: read_u64(0),
+            capacity: read_u64(SLOT_CAPACITY_OFFSET),
+            locked: fields[SLOT_LOCKED_OFFSET] == 1u8,
+            record_count: read_u64(SLOT_RECORD_COUNT_OFFSET),
+            sealed_at: read_u64(SLOT_SEALED_AT_OFFSET),
+            last_checksum: u32::from_le_bytes(
+                fields[SLOT_LAST_CHECKSUM_OFFSET..SLOT_METADATA_LENGTH_OFFSET].try_into().unwrap(),
+            ),
+            metadata_length: read_u64(SLOT_METADATA_LENGTH_OFFSET),
+            ..empty
+        };
+
+        if header.generation % HEADER_SLOTS != slot || header.metadata_length > header.metadata_capacity() as u64 {
+            return None;
+        }
+
+        let metadata_start = start + HEADER_SLOT_FIXED_SIZE;
+        let metadata = bytes.get(metadata_start..metadata_start + header.metadata_length as usize)?;
+        let checksum = u32::from_le_bytes(fields[SLOT_CHECKSUM_OFFSET..HEADER_SLOT_FIXED_SIZE].try_into().unwrap());
+
+        (Self::slot_checksum(&fields[..SLOT_CHECKSUM_OFFSET], metadata) == checksum).then_some(header)
+    }
+
+    fn slot_checksum(fields: &[u8], metadata: &[u8]) -> u32 {
+        crc32c::crc32c_append(crc32c::crc32c(fields), metadata)
+    }
+}
+
+#[cfg(test)]
+mod header_tests {
+    use crate::header::{DiskHeader, DISK_FORMAT_VERSION, DISK_HEADER_FIXED_SIZE, DISK_HEADER_PREFIX_SIZE};
+    use crate::DiskError;
+
+    #[test]
+    pub fn test_round_trip() {
+        let header = DiskHeader {
+            locked: true,
+            record_count: 12,
+            sealed_at: 34,
+            last_checksum: 56,
+            ..DiskHeader::new(4096, 8192, 9)
+        };
+
+        let bytes = header.to_bytes(&[7u8; 9]);
+        assert_eq!(bytes.len(), 4096);
+        assert!(DiskHeader::has_magic(&bytes));
+        assert_eq!(DiskHeader::from_bytes(&bytes), Ok(header));
+        assert_eq!(header.format_version, DISK_FORMAT_VERSION);
+        assert_eq!(header.metadata_capacity(), (4096 - DISK_HEADER_FIXED_SIZE) / 2);
+        assert_eq!(&bytes[header.metadata_offset()..header.metadata_offset() + 9], &[7u8; 9]);
+    }
+
+    #[test]
+    pub fn test_rejects_missing_magic() {
+        let mut bytes = DiskHeader::new(4096, 8192, 9).to_bytes(&[1u8; 9]);
+        assert_eq!(DiskHeader::from_bytes(&bytes[..10]), Err(DiskError::NotADiskFile));
+
+        bytes[0] = b'X';
+        assert!(!DiskHeader::has_magic(&bytes));
+        assert_eq!(DiskHeader::from_bytes(&bytes), Err(DiskError::NotADiskFile));
+    }
+
+    #[test]
+    pub fn test_probe() {
+        assert_eq!(DiskHeader::probe(&[]), Ok(None));
+        assert_eq!(DiskHeader::probe(&[0u8; 128]), Ok(None));
+        assert_eq!(DiskHeader::probe(b"hello"), Err(DiskError::NotADiskFile));
+
+        let mut zeroed = [0u8; 128];
+        zeroed[100] = 1;
+        assert_eq!(DiskHeader::probe(&zeroed), Err(DiskError::NotADiskFile));
+
+        let header = DiskHeader::new(4096, 8192, 9);
+        assert_eq!(DiskHeader::probe(&header.to_bytes(&[1u8; 9])), Ok(Some(header)));
+
+        let mut bytes = header.to_bytes(&[1u8; 9]);
+        bytes[8..10].copy_from_slice(&7u16.to_le_bytes());
+        assert_eq!(DiskHeader::probe(&bytes), Err(DiskError::UnsupportedVersion { version: 7 }));
+    }
+
+    #[test]
+    pub fn test_picks_latest_intact_slot() {
+        let first = DiskHeader::new(256, 8192, 9);
+        let second = DiskHeader { record_count: 3, ..first.next() };
+        assert_ne!(first.slot_offset(), second.slot_offset());
+        assert_eq!(second.next().slot_offset(), first.slot_offset());
+
+        let mut bytes = first.to_bytes(&[1u8; 9]);
+        let slot = second.slot_bytes(&[2u8; 9]);
+        bytes[second.slot_offset()..second.slot_offset() + slot.len()].copy_from_slice(&slot);
+        assert_eq!(DiskHeader::from_bytes(&bytes), Ok(second));
+
+        // Torn metadata of the latest slot, the previous one takes over
+        bytes[second.metadata_offset()] = 42;
+        assert_eq!(DiskHeader::from_bytes(&bytes), Ok(first));
+
+        // Torn fields of the only slot left
+        bytes[first.slot_offset()] ^= 0xFF;
+        assert_eq!(DiskHeader::from_bytes(&bytes), Err(DiskError::CorruptedHeader));
+
+        // Prefix alone, nothing was ever written to either slot
+        assert_eq!(DiskHeader::from_bytes(&bytes[..DISK_HEADER_PREFIX_SIZE]), Err(DiskError::CorruptedHeader));
+    }
+}
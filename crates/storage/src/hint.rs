@@ -0,0 +1,283 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use crate::cursor::Cursor;
+use crate::disk::Disk;
+use crate::keyed_record::{KeyedRecord, KeyedRecordKind};
+use crate::record::{RecordHeader, RecordLocation};
+use crate::utils::store_atomically;
+use crate::{DiskError, U64_SIZE};
+
+pub const HINT_FILE_EXTENSION: &str = "hint";
+pub const HINT_VERSION: u8 = 1;
+
+/// Version + Body Length + CRC32C
+pub const HINT_HEADER_SIZE: usize = 1 + 8 + 4;
+
+/// Created At + Write Offset
+pub const HINT_DISK_INFO_SIZE: usize = 8 + 8;
+
+/// Kind + Timestamp + Offset + Length + Checksum + Key Length
+pub const HINT_ENTRY_HEADER_SIZE: usize = 1 + 8 + 8 + 4 + 4 + 4;
+
+/// Latest write of a key within a sealed disk
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HintEntry {
+    pub kind: KeyedRecordKind,
+    pub timestamp: u64,
+    /// Location of the record inside the disk
+    pub location: RecordLocation,
+    /// CRC32C of the record, as found in its header
+    pub checksum: u32,
+    pub key: Vec<u8>,
+}
+
+impl HintEntry {
+    pub fn is_tombstone(&self) -> bool {
+        self.kind == KeyedRecordKind::Tombstone
+    }
+}
+
+/// Summary of the keys of a sealed disk, written next to it so that an index
+/// can be rebuilt without reading every record back.
+///
+/// | Byte Range | Description              | Details                            |
+/// |------------|--------------------------|------------------------------------|
+/// | 0          | Version (1 byte)         | `HINT_VERSION`                     |
+/// | 1-9        | Body Length (8 bytes)    | Length of the body in bytes        |
+/// | 9-13       | Checksum (4 bytes)       | CRC32C of the body                 |
+/// | 13-21      | Created At (8 bytes)     | `created_at` of the disk           |
+/// | 21-29      | Write Offset (8 bytes)   | End of the data of the disk        |
+/// | 29...      | Entries (variable)       | Ordered by offset                  |
+///
+/// Every entry is laid out as:
+///
+/// | Byte Range | Description              | Details                            |
+/// |------------|--------------------------|------------------------------------|
+/// | 0          | Kind (1 byte)            | 0 = Put, 1 = Tombstone             |
+/// | 1-9        | Timestamp (8 bytes)      | Seconds since UNIX epoch           |
+/// | 9-17       | Offset (8 bytes)         | Offset of the record header        |
+/// | 17-21      | Length (4 bytes)         | Length of the record payload       |
+/// | 21-25      | Checksum (4 bytes)       | CRC32C of the record               |
+/// | 25-29      | Key Length (4 bytes)     | Length of the key in bytes         |
+/// | 29...      | Key (variable)           |                                    |
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HintFile {
+    pub created_at: u64,
+    pub write_offset: u64,
+    pub entries: Vec<HintEntry>,
+}
+
+impl HintFile {
+    /// Hint file that goes along with the disk at `disk_path`
+    pub fn path(disk_path: &Path) -> PathBuf {
+        disk_path.with_extension(HINT_FILE_EXTENSION)
+    }
+
+    /// Scan the keyed records of `disk` and keep the latest write of every key,
+    /// tombstones included so that they still shadow older disks.
+    pub fn from_disk(disk: &Disk) -> Result<Self, DiskError> {
+        let mut positions: HashMap<Vec<u8>, usize> = HashMap::new();
+        let mut entries: Vec<Option<HintEntry>> = vec![];
+
+        for record in disk.iter() {
+            let record = record?;
+            let keyed = KeyedRecord::from_bytes(record.payload)?;
+            let entry = HintEntry {
+                kind: keyed.kind,
+                timestamp: keyed.timestamp,
+                location: record.location,
+                checksum: RecordHeader::checksum(record.location.length as u32, record.payload),
+                key: keyed.key.to_vec(),
+            };
+
+            if let Some(previous) = positions.insert(entry.key.clone(), entries.len()) {
+                entries[previous] = None;
+            }
+            entries.push(Some(entry));
+        }
+
+        Ok(Self {
+            created_at: disk.metadata().created_at(),
+            write_offset: disk.curr_writing_offset() as u64,
+            entries: entries.into_iter().flatten().collect(),
+        })
+    }
+
+    /// Whether this hint file was written for `disk` as it is now
+    pub fn matches(&self, disk: &Disk) -> bool {
+        self.created_at == disk.metadata().created_at()
+            && self.write_offset == disk.curr_writing_offset() as u64
+    }
+
+    pub fn to_vec(&self) -> Vec<u8> {
+        let mut body = Vec::with_capacity(
+            HINT_DISK_INFO_SIZE
+                + self
+                    .entries
+                    .iter()
+                    .map(|e| HINT_ENTRY_HEADER_SIZE + e.key.len())
+                    .sum::<usize>(),
+        );
+
+        body.extend_from_slice(&self.created_at.to_le_bytes());
+        body.extend_from_slice(&self.write_offset.to_le_bytes());
+
+        for entry in &self.entries {
+            body.push(entry.kind.get_le_identifier());
+            body.extend_from_slice(&entry.timestamp.to_le_bytes());
+            body.extend_from_slice(&(entry.location.offset as u64).to_le_bytes());
+            body.extend_from_slice(&(entry.location.length as u32).to_le_bytes());
+            body.extend_from_slice(&entry.checksum.to_le_bytes());
+            body.extend_from_slice(&(entry.key.len() as u32).to_le_bytes());
+            body.extend_from_slice(&entry.key);
+        }
+
+        let mut vec = Vec::with_capacity(HINT_HEADER_SIZE + body.len());
+        vec.push(HINT_VERSION);
+        vec.extend_from_slice(&(body.len() as u64).to_le_bytes());
+        vec.extend_from_slice(&crc32c::crc32c(&body).to_le_bytes());
+        vec.extend_from_slice(&body);
+
+        vec
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, DiskError> {
+        let mut cursor = Cursor::new(bytes);
+        let header = cursor
+            .consume(HINT_HEADER_SIZE)
+            .map_err(|_| DiskError::InvalidHintFile)?;
+
+        if header[0] != HINT_VERSION {
+            return Err(DiskError::InvalidHintFile);
+        }
+
+        let length = u64::from_le_bytes(header[1..9].try_into().unwrap()) as usize;
+        let checksum = u32::from_le_bytes(header[9..13].try_into().unwrap());
+        let body = cursor.consume(length).map_err(|_| DiskError::InvalidHintFile)?;
+
+        if crc32c::crc32c(body) != checksum {
+            return Err(DiskError::InvalidHintFile);
+        }
+
+        let mut cursor = Cursor::new(body);
+        let info = cursor
+            .consume(HINT_DISK_INFO_SIZE)
+            .map_err(|_| DiskError::InvalidHintFile)?;
+        let created_at = u64::from_le_bytes(info[0..U64_SIZE].try_into().unwrap());
+        let write_offset = u64::from_le_bytes(info[U64_SIZE..2 * U64_SIZE].try_into().unwrap());
+        let mut entries = vec![];
+
+        while cursor.position < body.len() {
+            let header = cursor
+                .consume(HINT_ENTRY_HEADER_SIZE)
+                .map_err(|_| DiskError::InvalidHintFile)?;
+
+            let kind = KeyedRecordKind::try_from(header[0]).map_err(|_| DiskError::InvalidHintFile)?;
+            let timestamp = u64::from_le_bytes(header[1..9].try_into().unwrap());
+            let offset = u64::from_le_bytes(header[9..17].try_into().unwrap()) as usize;
+            let length = u32::from_le_bytes(header[17..21].try_into().unwrap()) as usize;
+            let checksum = u32::from_le_bytes(header[21..25].try_into().unwrap());
+            let key_length = u32::from_le_bytes(header[25..29].try_into().unwrap()) as usize;
+            let key = cursor
+                .consume(key_length)
+                .map_err(|_| DiskError::InvalidHintFile)?;
+
+            entries.push(HintEntry {
+                kind,
+                timestamp,
+                location: RecordLocation { offset, length },
+                checksum,
+                key: key.to_vec(),
+            });
+        }
+
+        Ok(Self {
+            created_at,
+            write_offset,
+            entries,
+        })
+    }
+
+    /// Load the hint file at `path`, if there is one
+    pub async fn load(path: &Path) -> Result<Option<Self>, DiskError> {
+        match tokio::fs::read(path).await {
+            Ok(bytes) => Self::from_bytes(&bytes).map(Some),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(DiskError::io(path, e)),
+        }
+    }
+
+    /// Atomically write the hint file at `path`
+    pub async fn store(&self, path: &Path) -> Result<(), DiskError> {
+        let tmp_path = path.with_extension(format!("{}.tmp", HINT_FILE_EXTENSION));
+
+        store_atomically(path, &tmp_path, &self.to_vec()).await
+    }
+}
+
+#[cfg(test)]
+mod hint_tests {
+    use crate::disk::Disk;
+    use crate::hint::HintFile;
+    use crate::keyed_record::{KeyedRecord, KeyedRecordKind};
+    use crate::utils::test_utils::{disk_conf, get_file};
+    use crate::DiskError;
+
+    async fn sealed_disk() -> Disk {
+        let disk = Disk::open(disk_conf(get_file(None, true))).await.unwrap();
+
+        disk.append(&KeyedRecord::put(b"a", b"1").to_vec()).unwrap();
+        disk.append(&KeyedRecord::put(b"b", b"1").to_vec()).unwrap();
+        disk.append(&KeyedRecord::put(b"a", b"2").to_vec()).unwrap();
+        disk.append(&KeyedRecord::tombstone(b"c").to_vec()).unwrap();
+        disk.seal().await.unwrap();
+
+        disk
+    }
+
+    #[tokio::test]
+    pub async fn test_from_disk_keeps_latest_write() {
+        let disk = sealed_disk().await;
+        let hint = HintFile::from_disk(&disk).unwrap();
+        assert!(hint.matches(&disk));
+
+        let keys: Vec<(&[u8], KeyedRecordKind)> = hint
+            .entries
+            .iter()
+            .map(|e| (e.key.as_slice(), e.kind))
+            .collect();
+        assert_eq!(keys, vec![
+            (b"b".as_slice(), KeyedRecordKind::Put),
+            (b"a".as_slice(), KeyedRecordKind::Put),
+            (b"c".as_slice(), KeyedRecordKind::Tombstone),
+        ]);
+
+        let value = KeyedRecord::from_bytes(disk.read(hint.entries[1].location).unwrap()).unwrap().value;
+        assert_eq!(value, b"2");
+    }
+
+    #[tokio::test]
+    pub async fn test_store_and_load() {
+        let disk = sealed_disk().await;
+        let hint = HintFile::from_disk(&disk).unwrap();
+        let path = HintFile::path(&disk.path);
+
+        assert_eq!(HintFile::load(&path).await.unwrap(), None);
+        hint.store(&path).await.unwrap();
+        assert_eq!(HintFile::load(&path).await.unwrap(), Some(hint));
+    }
+
+    #[tokio::test]
+    pub async fn test_rejects_corrupted_hint() {
+        let hint = HintFile::from_disk(&sealed_disk().await).unwrap();
+
+        let mut bytes = hint.to_vec();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xFF;
+        assert_eq!(HintFile::from_bytes(&bytes), Err(DiskError::InvalidHintFile));
+
+        let mut bytes = hint.to_vec();
+        bytes[0] = 42;
+        assert_eq!(HintFile::from_bytes(&bytes), Err(DiskError::InvalidHintFile));
+    }
+}
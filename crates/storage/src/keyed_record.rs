@@ -0,0 +1,143 @@
+use std::time::SystemTime;
+use crate::cursor::Cursor;
+use crate::utils::get_created_at;
+use crate::{DiskError, U64_SIZE};
+
+/// Kind + Timestamp + Key Length
+pub const KEYED_RECORD_HEADER_SIZE: usize = 1 + 8 + 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyedRecordKind {
+    Put,
+    /// Marks the key as deleted
+    Tombstone,
+}
+
+impl KeyedRecordKind {
+    pub fn get_le_identifier(&self) -> u8 {
+        match self {
+            KeyedRecordKind::Put => 0u8,
+            KeyedRecordKind::Tombstone => 1u8,
+        }
+    }
+}
+
+impl TryFrom<u8> for KeyedRecordKind {
+    type Error = DiskError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0u8 => Ok(KeyedRecordKind::Put),
+            1u8 => Ok(KeyedRecordKind::Tombstone),
+            _ => Err(DiskError::InvalidKeyedRecord),
+        }
+    }
+}
+
+/// Payload of a record that carries a key.
+///
+/// | Byte Range | Description              | Details                           |
+/// |------------|--------------------------|-----------------------------------|
+/// | 0          | Kind (1 byte)            | 0 = Put, 1 = Tombstone            |
+/// | 1-9        | Timestamp (8 bytes)      | Seconds since UNIX epoch          |
+/// | 9-13       | Key Length (4 bytes)     | Length of the key in bytes        |
+/// | 13...      | Key (variable)           |                                   |
+/// | ...        | Value (variable)         | Empty for tombstones              |
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyedRecord<'a> {
+    pub kind: KeyedRecordKind,
+    pub timestamp: u64,
+    pub key: &'a [u8],
+    pub value: &'a [u8],
+}
+
+impl<'a> KeyedRecord<'a> {
+    pub fn put(key: &'a [u8], value: &'a [u8]) -> Self {
+        Self {
+            kind: KeyedRecordKind::Put,
+            timestamp: get_created_at(SystemTime::now()),
+            key,
+            value,
+        }
+    }
+
+    pub fn tombstone(key: &'a [u8]) -> Self {
+        Self {
+            kind: KeyedRecordKind::Tombstone,
+            timestamp: get_created_at(SystemTime::now()),
+            key,
+            value: &[],
+        }
+    }
+
+    pub fn is_tombstone(&self) -> bool {
+        self.kind == KeyedRecordKind::Tombstone
+    }
+
+    pub fn to_vec(&self) -> Vec<u8> {
+        let mut vec = Vec::with_capacity(KEYED_RECORD_HEADER_SIZE + self.key.len() + self.value.len());
+
+        vec.push(self.kind.get_le_identifier());
+        vec.extend_from_slice(&self.timestamp.to_le_bytes());
+        vec.extend_from_slice(&(self.key.len() as u32).to_le_bytes());
+        vec.extend_from_slice(self.key);
+        vec.extend_from_slice(self.value);
+
+        vec
+    }
+
+    pub fn from_bytes(bytes: &'a [u8]) -> Result<Self, DiskError> {
+        let mut cursor = Cursor::new(bytes);
+        let header = cursor
+            .consume(KEYED_RECORD_HEADER_SIZE)
+            .map_err(|_| DiskError::InvalidKeyedRecord)?;
+
+        let kind = KeyedRecordKind::try_from(header[0])?;
+        let timestamp = u64::from_le_bytes(header[1..1 + U64_SIZE].try_into().unwrap());
+        let key_length = u32::from_le_bytes(header[9..13].try_into().unwrap()) as usize;
+        let key = cursor
+            .consume(key_length)
+            .map_err(|_| DiskError::InvalidKeyedRecord)?;
+        let value = &bytes[cursor.position..];
+
+        Ok(Self {
+            kind,
+            timestamp,
+            key,
+            value,
+        })
+    }
+}
+
+#[cfg(test)]
+mod keyed_record_tests {
+    use crate::keyed_record::{KeyedRecord, KeyedRecordKind};
+    use crate::DiskError;
+
+    #[test]
+    pub fn test_round_trip() {
+        let put = KeyedRecord::put(b"key", b"value");
+        let bytes = put.to_vec();
+        assert_eq!(KeyedRecord::from_bytes(&bytes).unwrap(), put);
+
+        let tombstone = KeyedRecord::tombstone(b"key");
+        let bytes = tombstone.to_vec();
+        let decoded = KeyedRecord::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded.kind, KeyedRecordKind::Tombstone);
+        assert!(decoded.value.is_empty());
+    }
+
+    #[test]
+    pub fn test_rejects_malformed() {
+        assert_eq!(KeyedRecord::from_bytes(b"short"), Err(DiskError::InvalidKeyedRecord));
+
+        let mut bytes = KeyedRecord::put(b"key", b"value").to_vec();
+        bytes[0] = 9;
+        assert_eq!(KeyedRecord::from_bytes(&bytes), Err(DiskError::InvalidKeyedRecord));
+
+        // Key longer than what is left
+        let mut bytes = KeyedRecord::put(b"key", b"").to_vec();
+        bytes[9..13].copy_from_slice(&100u32.to_le_bytes());
+        assert_eq!(KeyedRecord::from_bytes(&bytes), Err(DiskError::InvalidKeyedRecord));
+    }
+}
@@ -0,0 +1,474 @@
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use dashmap::mapref::entry::Entry;
+use dashmap::DashMap;
+use crate::compaction::CompactionReport;
+use crate::hint::HintFile;
+use crate::keyed_record::KeyedRecord;
+use crate::segmented_log::{LogLocation, Segment, SegmentedLog, SegmentedLogConf};
+use crate::DiskError;
+
+pub type Key = Vec<u8>;
+
+/// Bitcask-style key-value store on top of a `SegmentedLog`.
+///
+/// Every `put` and `delete` is appended to the log as a `KeyedRecord`, while an
+/// in-memory index keeps the location of the latest value of each key.
+///
+/// On open, the index of sealed segments is rebuilt from their hint files and
+/// only the active segment is scanned. A sealed segment whose hint file is
+/// missing or stale is scanned once and gets a fresh one.
+///
+/// Writes to the same key are ordered by their offset in the log: the index only
+/// takes a write over a smaller offset. Deleted keys stay in it as tombstones
+/// until compaction drops them from the log, so a racing `put` can't bring them back.
+pub struct KvStore {
+    log: SegmentedLog,
+    index: DashMap<Key, IndexEntry>,
+    /// Writes below it were compacted, `compact` indexed them already
+    compacted_to: AtomicU64,
+}
+
+/// Latest write indexed for a key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct IndexEntry {
+    location: LogLocation,
+    tombstone: bool,
+}
+
+impl IndexEntry {
+    fn live(&self) -> Option<LogLocation> {
+        (!self.tombstone).then_some(self.location)
+    }
+}
+
+impl KvStore {
+    pub async fn open<P: AsRef<Path> + Clone>(opts: SegmentedLogConf<P>) -> Result<Self, DiskError> {
+        let store = Self {
+            log: SegmentedLog::open(opts).await?.with_hints(),
+            index: DashMap::new(),
+            compacted_to: AtomicU64::new(0),
+        };
+
+        for segment in store.log.segments().await {
+            if segment.disk.is_sealed() {
+                store.index_hints(&segment).await?;
+            } else {
+                store.index_segment(&segment)?;
+            }
+        }
+
+        // Nothing is racing with the replay
+        store.index.retain(|_, entry| !entry.tombstone);
+
+        Ok(store)
+    }
+
+    /// Index the write of `key` at `location` unless a later one is indexed already.
+    /// Returns whether it replaced a live value.
+    fn index_write(&self, key: &[u8], location: LogLocation, tombstone: bool) -> bool {
+        let entry = IndexEntry { location, tombstone };
+        let current = self.index.entry(key.to_vec());
+
+        // Compacted while it was being indexed, checked under the entry lock so
+        // that `compact` either sees this write or this write sees `compacted_to`
+        if location.offset < self.compacted_to.load(Ordering::SeqCst) {
+            return false;
+        }
+
+        match current {
+            Entry::Occupied(mut current) if current.get().location.offset < location.offset => {
+                !current.insert(entry).tombstone
+            }
+            Entry::Occupied(_) => false,
+            Entry::Vacant(vacant) => {
+                vacant.insert(entry);
+                false
+            }
+        }
+    }
+
+    /// Replay the hint file of the sealed `segment` into `index`
+    async fn index_hints(&self, segment: &Segment) -> Result<(), DiskError> {
+        let path = segment.hint_path();
+        let hint = match HintFile::load(&path).await {
+            Ok(Some(hint)) if hint.matches(&segment.disk) => hint,
+            Ok(_) | Err(DiskError::InvalidHintFile) => {
+                let hint = HintFile::from_disk(&segment.disk)?;
+                hint.store(&path).await?;
+                hint
+            }
+            Err(e) => return Err(e),
+        };
+
+        for entry in hint.entries {
+            self.index_write(&entry.key, segment.to_global(entry.location), entry.is_tombstone());
+        }
+
+        Ok(())
+    }
+
+    /// Replay the records of `segment` into `index`
+    fn index_segment(&self, segment: &Segment) -> Result<(), DiskError> {
+        for record in segment.disk.iter() {
+            let record = record?;
+            let keyed = KeyedRecord::from_bytes(record.payload)?;
+            self.index_write(keyed.key, segment.to_global(record.location), keyed.is_tombstone());
+        }
+
+        Ok(())
+    }
+
+    pub fn log(&self) -> &SegmentedLog {
+        &self.log
+    }
+
+    /// Number of live keys
+    pub fn len(&self) -> usize {
+        self.index.iter().filter(|entry| !entry.tombstone).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn contains_key(&self, key: &[u8]) -> bool {
+        self.location(key).is_some()
+    }
+
+    /// Where the latest value of `key` lives, if it has one
+    fn location(&self, key: &[u8]) -> Option<LogLocation> {
+        self.index.get(key).and_then(|entry| entry.live())
+    }
+
+    pub async fn put(&self, key: &[u8], value: &[u8]) -> Result<LogLocation, DiskError> {
+        let location = self.log.append(&KeyedRecord::put(key, value).to_vec()).await?;
+        self.index_write(key, location, false);
+
+        Ok(location)
+    }
+
+    pub async fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, DiskError> {
+        loop {
+            let Some(location) = self.location(key) else {
+                return Ok(None);
+            };
+
+            let payload = self.log.read(location).await;
+
+            if let Ok(payload) = &payload {
+                let keyed = KeyedRecord::from_bytes(payload)?;
+                if keyed.key == key {
+                    return Ok(Some(keyed.value.to_vec()));
+                }
+            }
+
+            // A compaction moved the record in the meantime, try again with the new location
+            if self.location(key) == Some(location) {
+                return Err(payload.err().unwrap_or(DiskError::InvalidLocation));
+            }
+        }
+    }
+
+    /// Remove `key`, returns whether it was there.
+    ///
+    /// The tombstone is appended even for an unknown key, a `put` of it may
+    /// be in the log already without being indexed yet.
+    pub async fn delete(&self, key: &[u8]) -> Result<bool, DiskError> {
+        let location = self.log.append(&KeyedRecord::tombstone(key).to_vec()).await?;
+
+        Ok(self.index_write(key, location, true))
+    }
+
+    /// Compact the sealed segments of the log and point the index at the compacted records.
+    ///
+    /// The index moves while the log still holds off readers, a `get` that raced
+    /// with it sees the location change and reads again.
+    pub async fn compact(&self) -> Result<Option<CompactionReport>, DiskError> {
+        self.log
+            .compact_with(|compacted, replaced_end| {
+                // Before any entry is touched, see `index_write`
+                self.compacted_to.fetch_max(replaced_end, Ordering::SeqCst);
+                let mut moved = HashSet::new();
+
+                // Only keys whose latest value lives in the replaced range move,
+                // including the ones of writes that are not indexed yet
+                for record in compacted.disk.iter() {
+                    let record = record?;
+                    let keyed = KeyedRecord::from_bytes(record.payload)?;
+                    let entry = IndexEntry { location: compacted.to_global(record.location), tombstone: false };
+
+                    self.index
+                        .entry(keyed.key.to_vec())
+                        .and_modify(|current| {
+                            if current.location.offset < replaced_end {
+                                *current = entry;
+                            }
+                        })
+                        .or_insert(entry);
+                    moved.insert(keyed.key.to_vec());
+                }
+
+                // What is left in the replaced range was deleted, tombstones included
+                self.index.retain(|key, entry| entry.location.offset >= replaced_end || moved.contains(key));
+
+                Ok(())
+            })
+            .await
+    }
+}
+
+#[cfg(test)]
+mod kv_store_tests {
+    use std::sync::Arc;
+    use crate::hint::HintFile;
+    use crate::keyed_record::KeyedRecord;
+    use crate::kv_store::KvStore;
+    use crate::segmented_log::SegmentedLogConf;
+    use crate::utils::test_utils::{get_folder, TEST_HEADER_SIZE};
+
+    fn conf() -> SegmentedLogConf<std::path::PathBuf> {
+        SegmentedLogConf {
+            dir: get_folder(None),
+            segment_capacity: 256,
+            segment_max_items: 4,
+            segment_header_size: TEST_HEADER_SIZE,
+        }
+    }
+
+    #[tokio::test]
+    pub async fn test_put_get_delete() {
+        let store = KvStore::open(conf()).await.unwrap();
+
+        store.put(b"a", b"1").await.unwrap();
+        store.put(b"b", b"1").await.unwrap();
+        store.put(b"a", b"2").await.unwrap();
+
+        assert_eq!(store.get(b"a").await.unwrap(), Some(b"2".to_vec()));
+        assert_eq!(store.get(b"b").await.unwrap(), Some(b"1".to_vec()));
+        assert_eq!(store.get(b"c").await.unwrap(), None);
+        assert_eq!(store.len(), 2);
+
+        assert!(store.delete(b"b").await.unwrap());
+        assert!(!store.delete(b"b").await.unwrap());
+        assert_eq!(store.get(b"b").await.unwrap(), None);
+        assert_eq!(store.len(), 1);
+    }
+
+    #[tokio::test]
+    pub async fn test_index_rebuilt_on_open() {
+        let conf = conf();
+        let store = KvStore::open(conf.clone()).await.unwrap();
+
+        for i in 0..10 {
+            store.put(format!("key-{}", i).as_bytes(), b"old").await.unwrap();
+        }
+        store.put(b"key-3", b"new").await.unwrap();
+        store.delete(b"key-7").await.unwrap();
+        assert!(store.log().segments().await.len() > 1);
+        store.log().flush().await.unwrap();
+        drop(store);
+
+        let store = KvStore::open(conf).await.unwrap();
+        assert_eq!(store.len(), 9);
+        assert_eq!(store.get(b"key-3").await.unwrap(), Some(b"new".to_vec()));
+        assert_eq!(store.get(b"key-5").await.unwrap(), Some(b"old".to_vec()));
+        assert_eq!(store.get(b"key-7").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    pub async fn test_index_rebuilt_from_hints() {
+        let conf = conf();
+        let store = KvStore::open(conf.clone()).await.unwrap();
+
+        for i in 0..10 {
+            store.put(format!("key-{}", i).as_bytes(), b"old").await.unwrap();
+        }
+        store.delete(b"key-1").await.unwrap();
+        store.put(b"key-2", b"new").await.unwrap();
+        store.log().flush().await.unwrap();
+
+        let segments = store.log().segments().await;
+        let (sealed, active) = segments.split_at(segments.len() - 1);
+        assert!(sealed.iter().all(|s| s.hint_path().exists()));
+        assert!(!active[0].hint_path().exists());
+
+        // A corrupted hint file is rebuilt from its segment
+        std::fs::write(sealed[0].hint_path(), b"garbage").unwrap();
+        let first_hint = sealed[0].hint_path();
+        drop(segments);
+        drop(store);
+
+        let store = KvStore::open(conf).await.unwrap();
+        assert_eq!(store.len(), 9);
+        assert_eq!(store.get(b"key-1").await.unwrap(), None);
+        assert_eq!(store.get(b"key-2").await.unwrap(), Some(b"new".to_vec()));
+        assert_eq!(store.get(b"key-9").await.unwrap(), Some(b"old".to_vec()));
+        assert!(HintFile::load(&first_hint).await.unwrap().is_some());
+
+        // Compaction leaves a single hint file behind, for the compacted segment
+        store.compact().await.unwrap().unwrap();
+        let hints = std::fs::read_dir(&store.log().dir)
+            .unwrap()
+            .filter(|e| e.as_ref().unwrap().path().extension().and_then(|e| e.to_str()) == Some("hint"))
+            .count();
+        assert_eq!(hints, 1);
+    }
+
+    #[tokio::test]
+    pub async fn test_compact_keeps_index_in_sync() {
+        let store = KvStore::open(conf()).await.unwrap();
+
+        for round in 0..3 {
+            for i in 0..4 {
+                store.put(format!("key-{}", i).as_bytes(), format!("{}", round).as_bytes()).await.unwrap();
+            }
+        }
+        store.delete(b"key-0").await.unwrap();
+
+        let report = store.compact().await.unwrap().unwrap();
+        assert!(report.dropped > 0);
+
+        assert_eq!(store.get(b"key-0").await.unwrap(), None);
+        for i in 1..4 {
+            assert_eq!(store.get(format!("key-{}", i).as_bytes()).await.unwrap(), Some(b"2".to_vec()));
+        }
+    }
+
+    #[tokio::test]
+    pub async fn test_get_during_compaction() {
+        let store = Arc::new(KvStore::open(conf()).await.unwrap());
+
+        for round in 0..3 {
+            for i in 0..4 {
+                store.put(format!("key-{}", i).as_bytes(), format!("{}", round).as_bytes()).await.unwrap();
+            }
+        }
+
+        let readers: Vec<_> = (0..4)
+            .map(|i| {
+                let store = store.clone();
+                tokio::spawn(async move {
+                    for _ in 0..100 {
+                        assert_eq!(store.get(format!("key-{}", i).as_bytes()).await.unwrap(), Some(b"2".to_vec()));
+                        tokio::task::yield_now().await;
+                    }
+                })
+            })
+            .collect();
+
+        store.compact().await.unwrap().unwrap();
+
+        for reader in readers {
+            reader.await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    pub async fn test_concurrent_put_and_delete_match_the_log() {
+        let conf = conf();
+        let store = Arc::new(KvStore::open(conf.clone()).await.unwrap());
+
+        let handles: Vec<_> = (0..40)
+            .map(|i| {
+                let store = store.clone();
+                tokio::spawn(async move {
+                    let key = format!("key-{}", i % 4);
+                    if i % 2 == 0 {
+                        store.put(key.as_bytes(), format!("{}", i).as_bytes()).await.unwrap();
+                    } else {
+                        store.delete(key.as_bytes()).await.unwrap();
+                    }
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle.await.unwrap();
+        }
+
+        let mut expected = vec![];
+        for i in 0..4 {
+            expected.push(store.get(format!("key-{}", i).as_bytes()).await.unwrap());
+        }
+        let len = store.len();
+        store.log().flush().await.unwrap();
+        drop(store);
+
+        // Whatever won in memory is what the log replays to
+        let store = KvStore::open(conf).await.unwrap();
+        assert_eq!(store.len(), len);
+        for (i, value) in expected.into_iter().enumerate() {
+            assert_eq!(store.get(format!("key-{}", i).as_bytes()).await.unwrap(), value);
+        }
+    }
+
+    #[tokio::test]
+    pub async fn test_delete_is_not_undone_by_an_older_put() {
+        let store = KvStore::open(conf()).await.unwrap();
+
+        let older = store.log().append(&KeyedRecord::put(b"a", b"1").to_vec()).await.unwrap();
+        assert!(!store.delete(b"a").await.unwrap());
+
+        // The put that appended first only gets to index now
+        store.index_write(b"a", older, false);
+        assert_eq!(store.get(b"a").await.unwrap(), None);
+        assert!(store.is_empty());
+    }
+
+    #[tokio::test]
+    pub async fn test_put_compacted_before_it_is_indexed() {
+        let store = KvStore::open(conf()).await.unwrap();
+        store.put(b"late", b"0").await.unwrap();
+
+        // The put is appended, then its segment rolls and gets compacted
+        let late = store.log().append(&KeyedRecord::put(b"late", b"1").to_vec()).await.unwrap();
+        for i in 0..8 {
+            store.put(format!("key-{}", i).as_bytes(), b"1").await.unwrap();
+        }
+        store.compact().await.unwrap().unwrap();
+
+        store.index_write(b"late", late, false);
+        assert_eq!(store.get(b"late").await.unwrap(), Some(b"1".to_vec()));
+    }
+
+    #[tokio::test]
+    pub async fn test_compact_drops_tombstones_from_the_index() {
+        let store = KvStore::open(conf()).await.unwrap();
+        store.put(b"a", b"1").await.unwrap();
+        store.delete(b"a").await.unwrap();
+        for i in 0..8 {
+            store.put(format!("key-{}", i).as_bytes(), b"1").await.unwrap();
+        }
+        assert!(store.index.contains_key(b"a".as_slice()));
+
+        store.compact().await.unwrap().unwrap();
+        assert!(!store.index.contains_key(b"a".as_slice()));
+        assert_eq!(store.len(), 8);
+        assert_eq!(store.get(b"a").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    pub async fn test_concurrent_puts() {
+        let store = Arc::new(KvStore::open(conf()).await.unwrap());
+
+        let handles: Vec<_> = (0..50)
+            .map(|i| {
+                let store = store.clone();
+                tokio::spawn(async move {
+                    store.put(format!("key-{}", i).as_bytes(), format!("{}", i).as_bytes()).await.unwrap();
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle.await.unwrap();
+        }
+
+        assert_eq!(store.len(), 50);
+        for i in 0..50 {
+            assert_eq!(store.get(format!("key-{}", i).as_bytes()).await.unwrap(), Some(format!("{}", i).into_bytes()));
+        }
+    }
+}
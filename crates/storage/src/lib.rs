@@ -1,11 +1,26 @@
+use std::path::{Path, PathBuf};
 use enum_as_inner::EnumAsInner;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
-mod disk;
+pub mod disk;
+pub mod disk_iterator;
+pub mod disk_reader;
 mod utils;
-mod cursor;
-mod disk_metadata;
+pub mod cursor;
+pub mod disk_metadata;
+pub mod header;
+pub mod record;
+pub mod reservation;
+pub mod manifest;
+pub mod segmented_log;
+pub mod retention;
+pub mod keyed_record;
+pub mod compaction;
+pub mod kv_store;
+pub mod writer_lock;
+pub mod hint;
+pub mod durability;
 
 pub const U64_SIZE: usize = size_of::<u64>();
 
@@ -17,5 +32,130 @@ pub enum DiskError {
     InvalidFlushing,
     #[error("No more bytes allowed")]
     CapacityReached,
+    #[error("No more records allowed")]
+    MaxItemsReached,
+    #[error("No committed record at the given location")]
+    InvalidLocation,
+    #[error("Reserved {reserved} bytes but tried to commit {actual}")]
+    ReservationSizeMismatch {
+        reserved: usize,
+        actual: usize,
+    },
+    #[error("Record is larger than the maximum record size")]
+    RecordTooLarge,
+    #[error("Record at offset {offset} is corrupted, expected checksum {expected} but got {actual}")]
+    Corrupted {
+        offset: usize,
+        expected: u32,
+        actual: u32,
+    },
+    #[error("Record payload is not a valid keyed record")]
+    InvalidKeyedRecord,
+    #[error("I/O error ({kind}) on {path:?}: {message}")]
+    Io {
+        path: PathBuf,
+        #[serde(with = "io_error_kind")]
+        kind: std::io::ErrorKind,
+        message: String,
+    },
+    #[error("The disk is already open for writing in another process (pid {pid:?})")]
+    LockedByOtherWriter {
+        pid: Option<u32>,
+    },
+    #[error("The file is not a shugart disk")]
+    NotADiskFile,
+    #[error("Neither header slot of the disk is intact")]
+    CorruptedHeader,
+    #[error("Unsupported disk format version {version}")]
+    UnsupportedVersion {
+        version: u16,
+    },
+    #[error("The disk header or metadata is malformed")]
+    InvalidMetadata,
+    #[error("Cannot shrink a disk of {current} bytes to {requested} bytes")]
+    CapacityShrink {
+        current: u64,
+        requested: u64,
+    },
+    #[error("The metadata does not fit in the disk header")]
+    MetadataTooLarge,
+    #[error("The manifest is malformed or corrupted")]
+    InvalidManifest,
+    #[error("The hint file is malformed or corrupted")]
+    InvalidHintFile,
+    #[error("Segments must allow at least one record and be larger than their header")]
+    InvalidSegmentConf,
+    #[error("Unknown metadata version {version}")]
+    UnknownMetadataVersion {
+        version: u8,
+    },
+}
 
-}
\ No newline at end of file
+impl DiskError {
+    pub(crate) fn io(path: &Path, error: std::io::Error) -> Self {
+        DiskError::Io {
+            path: path.to_path_buf(),
+            kind: error.kind(),
+            message: error.to_string(),
+        }
+    }
+}
+
+/// `std::io::ErrorKind` goes by its name, kinds this list doesn't know come back as `Other`
+mod io_error_kind {
+    use std::io::ErrorKind;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    const KINDS: &[ErrorKind] = &[
+        ErrorKind::NotFound,
+        ErrorKind::PermissionDenied,
+        ErrorKind::AlreadyExists,
+        ErrorKind::WouldBlock,
+        ErrorKind::NotADirectory,
+        ErrorKind::IsADirectory,
+        ErrorKind::DirectoryNotEmpty,
+        ErrorKind::ReadOnlyFilesystem,
+        ErrorKind::StorageFull,
+        ErrorKind::FileTooLarge,
+        ErrorKind::ResourceBusy,
+        ErrorKind::InvalidInput,
+        ErrorKind::InvalidData,
+        ErrorKind::TimedOut,
+        ErrorKind::WriteZero,
+        ErrorKind::Interrupted,
+        ErrorKind::Unsupported,
+        ErrorKind::UnexpectedEof,
+        ErrorKind::OutOfMemory,
+        ErrorKind::Other,
+    ];
+
+    pub fn serialize<S: Serializer>(kind: &ErrorKind, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&format_args!("{:?}", kind))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<ErrorKind, D::Error> {
+        let name = String::deserialize(deserializer)?;
+
+        Ok(KINDS
+            .iter()
+            .copied()
+            .find(|kind| format!("{:?}", kind) == name)
+            .unwrap_or(ErrorKind::Other))
+    }
+}
+
+#[cfg(test)]
+mod disk_error_tests {
+    use std::io::ErrorKind;
+    use std::path::Path;
+    use crate::DiskError;
+
+    #[test]
+    pub fn test_io_error_keeps_its_kind() {
+        let error = DiskError::io(Path::new("a.disk"), std::io::Error::from(ErrorKind::PermissionDenied));
+        assert!(matches!(error, DiskError::Io { kind: ErrorKind::PermissionDenied, .. }));
+
+        let json = serde_json::to_string(&error).unwrap();
+        assert_eq!(serde_json::from_str::<DiskError>(&json).unwrap(), error);
+    }
+}
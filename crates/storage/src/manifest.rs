@@ -0,0 +1,172 @@
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use crate::cursor::Cursor;
+use crate::utils::store_atomically;
+use crate::DiskError;
+
+pub const MANIFEST_FILE_NAME: &str = "MANIFEST";
+pub const MANIFEST_VERSION: u8 = 1;
+
+/// Version + Body Length + CRC32C
+pub const MANIFEST_HEADER_SIZE: usize = 1 + 8 + 4;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub id: u64,
+    /// File name, relative to the data directory
+    pub file_name: String,
+    pub base_offset: u64,
+    /// Global offset past the last record of the segment. Only known once it
+    /// is sealed, until then it is as far as the segment could ever go.
+    #[serde(default)]
+    pub end_offset: u64,
+    pub sealed: bool,
+    pub created_at: u64,
+}
+
+/// Lists the segments that make up a data directory.
+///
+/// | Byte Range | Description              | Details                          |
+/// |------------|--------------------------|----------------------------------|
+/// | 0          | Version (1 byte)         | `MANIFEST_VERSION`               |
+/// | 1-9        | Body Length (8 bytes)    | Length of the JSON body in bytes |
+/// | 9-13       | Checksum (4 bytes)       | CRC32C of the body               |
+/// | 13...      | Body (variable)          | JSON encoded entries             |
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub segments: Vec<ManifestEntry>,
+}
+
+/// What was found when checking a data directory against its manifest.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ManifestReport {
+    /// Listed in the manifest but not on disk
+    pub missing: Vec<ManifestEntry>,
+    /// Segment files on disk the manifest does not know about
+    pub orphaned: Vec<PathBuf>,
+    /// Listed segments whose header could not be validated
+    pub invalid: Vec<(ManifestEntry, DiskError)>,
+}
+
+impl ManifestReport {
+    pub fn is_clean(&self) -> bool {
+        self.missing.is_empty() && self.orphaned.is_empty() && self.invalid.is_empty()
+    }
+}
+
+impl Manifest {
+    pub fn path(dir: &Path) -> PathBuf {
+        dir.join(MANIFEST_FILE_NAME)
+    }
+
+    pub fn to_vec(&self) -> Vec<u8> {
+        let body = serde_json::to_vec(self).unwrap();
+        let mut vec = Vec::with_capacity(MANIFEST_HEADER_SIZE + body.len());
+
+        vec.push(MANIFEST_VERSION);
+        vec.extend_from_slice(&(body.len() as u64).to_le_bytes());
+        vec.extend_from_slice(&crc32c::crc32c(&body).to_le_bytes());
+        vec.extend_from_slice(&body);
+
+        vec
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, DiskError> {
+        let mut cursor = Cursor::new(bytes);
+        let header = cursor
+            .consume(MANIFEST_HEADER_SIZE)
+            .map_err(|_| DiskError::InvalidManifest)?;
+
+        if header[0] != MANIFEST_VERSION {
+            return Err(DiskError::InvalidManifest);
+        }
+
+        let length = u64::from_le_bytes(header[1..9].try_into().unwrap()) as usize;
+        let checksum = u32::from_le_bytes(header[9..13].try_into().unwrap());
+        let body = cursor.consume(length).map_err(|_| DiskError::InvalidManifest)?;
+
+        if crc32c::crc32c(body) != checksum {
+            return Err(DiskError::InvalidManifest);
+        }
+
+        serde_json::from_slice(body).map_err(|_| DiskError::InvalidManifest)
+    }
+
+    /// Load the manifest of `dir`, if there is one
+    pub async fn load(dir: &Path) -> Result<Option<Self>, DiskError> {
+        let path = Self::path(dir);
+
+        match tokio::fs::read(&path).await {
+            Ok(bytes) => Self::from_bytes(&bytes).map(Some),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(DiskError::io(&path, e)),
+        }
+    }
+
+    /// Atomically replace the manifest of `dir`: write a temporary file,
+    /// sync it and rename it over the previous one.
+    pub async fn store(&self, dir: &Path) -> Result<(), DiskError> {
+        let path = Self::path(dir);
+        let tmp_path = dir.join(format!("{}.tmp", MANIFEST_FILE_NAME));
+
+        store_atomically(&path, &tmp_path, &self.to_vec()).await
+    }
+}
+
+#[cfg(test)]
+mod manifest_tests {
+    use crate::manifest::{Manifest, ManifestEntry};
+    use crate::utils::test_utils::get_folder;
+    use crate::DiskError;
+
+    fn manifest() -> Manifest {
+        Manifest {
+            segments: vec![
+                ManifestEntry {
+                    id: 0,
+                    file_name: String::from("0.disk"),
+                    base_offset: 0,
+                    end_offset: 128,
+                    sealed: true,
+                    created_at: 10,
+                },
+                ManifestEntry {
+                    id: 1,
+                    file_name: String::from("1.disk"),
+                    base_offset: 128,
+                    end_offset: 1024,
+                    sealed: false,
+                    created_at: 20,
+                },
+            ],
+        }
+    }
+
+    #[tokio::test]
+    pub async fn test_store_and_load() {
+        let dir = get_folder(None);
+        assert_eq!(Manifest::load(&dir).await.unwrap(), None);
+
+        manifest().store(&dir).await.unwrap();
+        assert_eq!(Manifest::load(&dir).await.unwrap(), Some(manifest()));
+
+        // Replacing leaves no temporary file behind
+        Manifest::default().store(&dir).await.unwrap();
+        assert_eq!(Manifest::load(&dir).await.unwrap(), Some(Manifest::default()));
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+    }
+
+    #[test]
+    pub fn test_rejects_corrupted_manifest() {
+        let mut bytes = manifest().to_vec();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xFF;
+        assert_eq!(Manifest::from_bytes(&bytes), Err(DiskError::InvalidManifest));
+
+        let mut bytes = manifest().to_vec();
+        bytes[0] = 42;
+        assert_eq!(Manifest::from_bytes(&bytes), Err(DiskError::InvalidManifest));
+
+        assert_eq!(Manifest::from_bytes(&[1, 2]), Err(DiskError::InvalidManifest));
+    }
+}
@@ -0,0 +1,160 @@
+use std::sync::atomic::{AtomicU8, Ordering};
+use serde::{Deserialize, Serialize};
+use crate::cursor::Cursor;
+use crate::DiskError;
+
+/// Payload Length + Flags + CRC32C
+pub const RECORD_HEADER_SIZE: usize = 4 + 1 + 4;
+
+/// Set once the payload and length of a record are fully written.
+/// The flags byte is published last, see `RecordHeader::load`.
+pub const RECORD_FLAG_COMMITTED: u8 = 1;
+
+/// Space that was reserved but never committed. Readers skip over it.
+pub const RECORD_FLAG_HOLE: u8 = 2;
+
+/// Space reserved by a `Reservation` that is not committed yet. Readers stop
+/// there, recovery knows its length and skips over it.
+pub const RECORD_FLAG_PENDING: u8 = 4;
+
+/// | Byte Range | Description                 | Details                              |
+/// |------------|-----------------------------|--------------------------------------|
+/// | 0-4        | Payload length (4 bytes)    | Length of the payload in bytes       |
+/// | 4          | Flags (1 byte)              | See `RECORD_FLAG_*`                  |
+/// | 5-9        | Checksum (4 bytes)          | CRC32C of the length and payload     |
+/// | 9...       | Payload (variable)          | The actual record bytes              |
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecordHeader {
+    pub length: u32,
+    pub flags: u8,
+    pub checksum: u32,
+}
+
+impl RecordHeader {
+    pub fn new(length: u32, flags: u8) -> Self {
+        Self { length, flags, checksum: 0 }
+    }
+
+    /// Build the header for `payload`, checksum included
+    pub fn for_payload(payload: &[u8], flags: u8) -> Result<Self, DiskError> {
+        let length = u32::try_from(payload.len()).map_err(|_| DiskError::RecordTooLarge)?;
+
+        Ok(Self {
+            length,
+            flags,
+            checksum: Self::checksum(length, payload),
+        })
+    }
+
+    pub fn checksum(length: u32, payload: &[u8]) -> u32 {
+        let crc = crc32c::crc32c(&length.to_le_bytes());
+        crc32c::crc32c_append(crc, payload)
+    }
+
+    /// Make sure `payload` is what was originally written for the record at `offset`
+    pub fn verify(&self, offset: usize, payload: &[u8]) -> Result<(), DiskError> {
+        let actual = Self::checksum(self.length, payload);
+
+        if actual != self.checksum {
+            return Err(DiskError::Corrupted {
+                offset,
+                expected: self.checksum,
+                actual,
+            });
+        }
+
+        Ok(())
+    }
+
+    pub fn is_committed(&self) -> bool {
+        self.flags & RECORD_FLAG_COMMITTED == RECORD_FLAG_COMMITTED
+    }
+
+    pub fn is_hole(&self) -> bool {
+        self.flags & RECORD_FLAG_HOLE == RECORD_FLAG_HOLE
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.flags & RECORD_FLAG_PENDING == RECORD_FLAG_PENDING
+    }
+
+    pub fn to_bytes(&self) -> [u8; RECORD_HEADER_SIZE] {
+        let mut bytes = [0u8; RECORD_HEADER_SIZE];
+        bytes[0..4].copy_from_slice(&self.length.to_le_bytes());
+        bytes[4] = self.flags;
+        bytes[5..9].copy_from_slice(&self.checksum.to_le_bytes());
+        bytes
+    }
+
+    /// Offset of the flags byte inside the header
+    pub const FLAGS_OFFSET: usize = 4;
+
+    /// Read a header a writer may be committing right now: the flags are loaded
+    /// first, pairing with the release store of the commit, so a committed
+    /// header always comes with its length, checksum and payload.
+    pub fn load(bytes: &[u8]) -> Self {
+        let flags = &bytes[..RECORD_HEADER_SIZE][Self::FLAGS_OFFSET];
+        // SAFETY: `AtomicU8` has the layout of `u8`, writers only touch the byte atomically
+        let flags = unsafe { &*(flags as *const u8 as *const AtomicU8) }.load(Ordering::Acquire);
+
+        Self { flags, ..Self::from_bytes(bytes) }
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        let length = u32::from_le_bytes(bytes[0..4].try_into().unwrap());
+        let checksum = u32::from_le_bytes(bytes[5..9].try_into().unwrap());
+        Self {
+            length,
+            flags: bytes[4],
+            checksum,
+        }
+    }
+}
+
+/// Where a record lives inside a `Disk`.
+/// `offset` points at the record header, `length` is the payload length.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct RecordLocation {
+    pub offset: usize,
+    pub length: usize,
+}
+
+impl RecordLocation {
+    pub fn payload_offset(&self) -> usize {
+        self.offset + RECORD_HEADER_SIZE
+    }
+
+    /// Header + payload
+    pub fn size(&self) -> usize {
+        RECORD_HEADER_SIZE + self.length
+    }
+
+    /// Read the payload this location points at.
+    /// The cursor length is expected to be the high-water mark of written data.
+    pub fn read<'a>(&self, mut cursor: Cursor<'a>) -> Result<&'a [u8], DiskError> {
+        cursor.move_to(self.offset);
+
+        let header_bytes = cursor
+            .consume(RECORD_HEADER_SIZE)
+            .map_err(|_| DiskError::InvalidLocation)?;
+        let header = RecordHeader::load(header_bytes);
+
+        if !header.is_committed() || header.length as usize != self.length {
+            return Err(DiskError::InvalidLocation);
+        }
+
+        let payload = cursor
+            .consume(self.length)
+            .map_err(|_| DiskError::InvalidLocation)?;
+        header.verify(self.offset, payload)?;
+
+        Ok(payload)
+    }
+}
+
+/// A committed record as read back from a `Disk`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DiskRecord<'a> {
+    pub location: RecordLocation,
+    pub payload: &'a [u8],
+}
@@ -0,0 +1,67 @@
+use crate::disk::{Disk, WriteGuard};
+use crate::record::{RecordHeader, RecordLocation, RECORD_FLAG_COMMITTED};
+use crate::DiskError;
+
+/// Space claimed on a `Disk` for a single record.
+///
+/// The record only becomes visible to readers once `commit` writes its header.
+/// A reservation dropped without being committed is marked as a hole, so
+/// readers and recovery skip over it instead of stopping there.
+///
+/// A reservation counts as an in-flight write until it is committed or dropped,
+/// so sealing the disk waits for it.
+pub struct Reservation<'a> {
+    disk: &'a Disk,
+    location: RecordLocation,
+    committed: bool,
+    _guard: WriteGuard<'a>,
+}
+
+impl<'a> Reservation<'a> {
+    pub(crate) fn new(disk: &'a Disk, guard: WriteGuard<'a>, location: RecordLocation) -> Self {
+        Self {
+            disk,
+            location,
+            committed: false,
+            _guard: guard,
+        }
+    }
+
+    pub fn location(&self) -> RecordLocation {
+        self.location
+    }
+
+    /// Write `data` into the reserved space and publish it.
+    /// `data` must be exactly as long as the reservation.
+    pub fn commit(mut self, data: &[u8]) -> Result<RecordLocation, DiskError> {
+        if data.len() != self.location.length {
+            return Err(DiskError::ReservationSizeMismatch {
+                reserved: self.location.length,
+                actual: data.len(),
+            });
+        }
+
+        let header = RecordHeader::for_payload(data, RECORD_FLAG_COMMITTED)?;
+
+        // Payload, length and checksum first, the flags (the commit marker) last
+        let bytes = header.to_bytes();
+        let flags_offset = self.location.offset + RecordHeader::FLAGS_OFFSET;
+        self.disk.copy_into(data, self.location.payload_offset());
+        self.disk.copy_into(&bytes[..RecordHeader::FLAGS_OFFSET], self.location.offset);
+        self.disk.copy_into(&bytes[RecordHeader::FLAGS_OFFSET + 1..], flags_offset + 1);
+        self.disk.publish_flags(flags_offset, header.flags);
+        self.disk.mark_committed(self.location);
+
+        self.committed = true;
+
+        Ok(self.location)
+    }
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        if !self.committed {
+            self.disk.abandon(self.location);
+        }
+    }
+}
@@ -0,0 +1,176 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use crate::segmented_log::Segment;
+use crate::utils::get_created_at;
+
+/// What to do with a segment that fell out of retention
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RetentionAction {
+    Delete,
+    /// Move the segment file into the given directory
+    Archive(PathBuf),
+}
+
+/// Limits on how much sealed data a `SegmentedLog` keeps around.
+///
+/// Only sealed segments are ever removed, oldest first, and the active
+/// segment never counts as a candidate.
+#[derive(Debug, Clone)]
+pub struct RetentionPolicy {
+    /// Remove segments created longer ago than this
+    pub max_age: Option<Duration>,
+    /// Keep the total on-disk size of all segments under this
+    pub max_total_bytes: Option<u64>,
+    /// Keep at most this many segments, the active one included
+    pub max_segments: Option<usize>,
+    pub action: RetentionAction,
+}
+
+impl RetentionPolicy {
+    /// How many of the oldest `segments` should be removed.
+    ///
+    /// `veto` is asked about every candidate before it is picked, returning `true`
+    /// keeps it (e.g. while a peer is still streaming from it). Removal stops at
+    /// the first vetoed segment so that the log only ever loses its oldest data.
+    pub fn select<F>(&self, segments: &[Arc<Segment>], now: SystemTime, veto: F) -> usize
+    where
+        F: Fn(&Segment) -> bool,
+    {
+        let now = get_created_at(now);
+        let mut remaining_segments = segments.len();
+        let mut remaining_bytes: u64 = segments.iter().map(|s| s.disk.capacity).sum();
+        let mut selected = 0;
+
+        // The active segment is never removed
+        for segment in segments.iter().take(segments.len().saturating_sub(1)) {
+            if !segment.disk.is_sealed() {
+                break;
+            }
+
+            let too_old = self
+                .max_age
+                .is_some_and(|max_age| segment.disk.metadata().created_at() + max_age.as_secs() <= now);
+            let too_big = self.max_total_bytes.is_some_and(|max| remaining_bytes > max);
+            let too_many = self.max_segments.is_some_and(|max| remaining_segments > max);
+
+            if !(too_old || too_big || too_many) || veto(segment) {
+                break;
+            }
+
+            selected += 1;
+            remaining_segments -= 1;
+            remaining_bytes -= segment.disk.capacity;
+        }
+
+        selected
+    }
+}
+
+#[cfg(test)]
+mod retention_tests {
+    use std::time::{Duration, SystemTime};
+    use crate::retention::{RetentionAction, RetentionPolicy};
+    use crate::segmented_log::{SegmentedLog, SegmentedLogConf};
+    use crate::utils::test_utils::{get_folder, TEST_HEADER_SIZE};
+
+    fn policy() -> RetentionPolicy {
+        RetentionPolicy {
+            max_age: None,
+            max_total_bytes: None,
+            max_segments: None,
+            action: RetentionAction::Delete,
+        }
+    }
+
+    async fn log_with_segments(count: usize) -> SegmentedLog {
+        let log = SegmentedLog::open(SegmentedLogConf {
+            dir: get_folder(None),
+            segment_capacity: 256,
+            segment_max_items: 1,
+            segment_header_size: TEST_HEADER_SIZE,
+        }).await.unwrap();
+
+        for i in 0..count {
+            log.append(format!("{}", i).as_bytes()).await.unwrap();
+        }
+
+        log
+    }
+
+    #[tokio::test]
+    pub async fn test_select() {
+        let log = log_with_segments(5).await;
+        let segments = log.segments().await;
+        let now = SystemTime::now();
+        assert_eq!(segments.len(), 5);
+
+        assert_eq!(policy().select(&segments, now, |_| false), 0);
+
+        let by_count = RetentionPolicy { max_segments: Some(3), ..policy() };
+        assert_eq!(by_count.select(&segments, now, |_| false), 2);
+
+        let by_size = RetentionPolicy { max_total_bytes: Some(256 * 4), ..policy() };
+        assert_eq!(by_size.select(&segments, now, |_| false), 1);
+
+        // Everything is expired, but the active segment stays
+        let by_age = RetentionPolicy { max_age: Some(Duration::ZERO), ..policy() };
+        assert_eq!(by_age.select(&segments, now, |_| false), 4);
+        let by_age = RetentionPolicy { max_age: Some(Duration::from_secs(3600)), ..policy() };
+        assert_eq!(by_age.select(&segments, now, |_| false), 0);
+
+        // A vetoed segment stops removal there
+        let by_count = RetentionPolicy { max_segments: Some(1), ..policy() };
+        assert_eq!(by_count.select(&segments, now, |s| s.id == 2), 2);
+        assert_eq!(by_count.select(&segments, now, |s| s.id == 0), 0);
+    }
+
+    #[tokio::test]
+    pub async fn test_apply_deletes_oldest() {
+        let log = log_with_segments(4).await;
+        let before = log.segments().await;
+
+        let removed = log
+            .apply_retention(&RetentionPolicy { max_segments: Some(2), ..policy() }, |_| false)
+            .await
+            .unwrap();
+
+        assert_eq!(removed.iter().map(|e| e.id).collect::<Vec<_>>(), vec![0, 1]);
+        assert!(!before[0].disk.path.exists());
+        assert!(!before[1].disk.path.exists());
+        assert!(before[2].disk.path.exists());
+
+        let after = log.segments().await;
+        assert_eq!(after.iter().map(|s| s.id).collect::<Vec<_>>(), vec![2, 3]);
+
+        // The manifest no longer lists them
+        let dir = log.dir.clone();
+        drop((before, after, log));
+        let reopened = SegmentedLog::open(SegmentedLogConf {
+            dir,
+            segment_capacity: 256,
+            segment_max_items: 1,
+            segment_header_size: TEST_HEADER_SIZE,
+        }).await.unwrap();
+        assert!(reopened.manifest_report().is_clean());
+        assert_eq!(reopened.segments().await.len(), 2);
+    }
+
+    #[tokio::test]
+    pub async fn test_apply_archives() {
+        let log = log_with_segments(3).await;
+        let archive = get_folder(Some(String::from("archive")));
+        let oldest = log.segments().await[0].disk.path.clone();
+
+        let policy = RetentionPolicy {
+            max_segments: Some(2),
+            action: RetentionAction::Archive(archive.clone()),
+            ..policy()
+        };
+        let removed = log.apply_retention(&policy, |_| false).await.unwrap();
+
+        assert_eq!(removed.len(), 1);
+        assert!(!oldest.exists());
+        assert!(archive.join(&removed[0].file_name).exists());
+    }
+}
@@ -0,0 +1,744 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+use uuid::Uuid;
+use crate::compaction::{compact_disks, CompactionReport};
+use crate::disk::{Disk, DiskConf};
+use crate::durability::Durability;
+use crate::header::DiskHeader;
+use crate::hint::HintFile;
+use crate::manifest::{Manifest, ManifestEntry, ManifestReport};
+use crate::record::RecordLocation;
+use crate::retention::{RetentionAction, RetentionPolicy};
+use crate::DiskError;
+
+pub const SEGMENT_FILE_EXTENSION: &str = "disk";
+
+#[derive(Clone)]
+pub struct SegmentedLogConf<P: AsRef<Path> + Clone> {
+    pub dir: P,
+    pub segment_capacity: u64,
+    pub segment_max_items: u64,
+    pub segment_header_size: u64,
+}
+
+/// Where a record lives across all the segments of a `SegmentedLog`.
+/// `offset` only ever grows, no matter which segment the record ended up in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct LogLocation {
+    pub offset: u64,
+    pub length: usize,
+}
+
+/// One `Disk` file of a `SegmentedLog`.
+pub struct Segment {
+    pub id: u64,
+    /// Global offset of the first byte of data in this segment
+    pub base_offset: u64,
+    pub disk: Disk,
+}
+
+impl Segment {
+    pub fn file_name(id: u64, base_offset: u64) -> String {
+        format!("{:010}_{:020}.{}", id, base_offset, SEGMENT_FILE_EXTENSION)
+    }
+
+    /// Compacted segments take over the id and base offset of the segments they
+    /// replace, a unique suffix keeps their file apart from the originals
+    pub fn compacted_file_name(id: u64, base_offset: u64) -> String {
+        format!("{:010}_{:020}_{}.{}", id, base_offset, Uuid::new_v4().simple(), SEGMENT_FILE_EXTENSION)
+    }
+
+    /// Parse `<id>_<base offset>[_<suffix>].disk`
+    pub fn parse_file_name(name: &str) -> Option<(u64, u64)> {
+        let stem = name.strip_suffix(&format!(".{}", SEGMENT_FILE_EXTENSION))?;
+        let mut parts = stem.splitn(3, '_');
+        let id = parts.next()?.parse().ok()?;
+        let base_offset = parts.next()?.parse().ok()?;
+        Some((id, base_offset))
+    }
+
+    pub fn to_global(&self, location: RecordLocation) -> LogLocation {
+        LogLocation {
+            offset: self.base_offset + (location.offset - self.disk.data_start()) as u64,
+            length: location.length,
+        }
+    }
+
+    pub fn to_local(&self, location: LogLocation) -> RecordLocation {
+        RecordLocation {
+            offset: (location.offset - self.base_offset) as usize + self.disk.data_start(),
+            length: location.length,
+        }
+    }
+
+    pub fn manifest_entry(&self) -> ManifestEntry {
+        ManifestEntry {
+            id: self.id,
+            file_name: self
+                .disk
+                .path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .unwrap_or_default()
+                .to_string(),
+            base_offset: self.base_offset,
+            end_offset: match self.disk.is_sealed() {
+                true => self.next_base_offset(),
+                false => self.base_offset + self.disk.capacity,
+            },
+            sealed: self.disk.is_sealed(),
+            created_at: self.disk.metadata().created_at(),
+        }
+    }
+
+    pub fn hint_path(&self) -> PathBuf {
+        HintFile::path(&self.disk.path)
+    }
+
+    /// Write the hint file of this segment, which must only hold keyed records
+    pub async fn write_hint(&self) -> Result<(), DiskError> {
+        HintFile::from_disk(&self.disk)?.store(&self.hint_path()).await
+    }
+
+    /// Global offset the next segment starts at
+    pub fn next_base_offset(&self) -> u64 {
+        self.base_offset + self.disk.used_bytes()
+    }
+}
+
+/// An append-only log spread over a directory of `Disk` segments.
+///
+/// Only the last segment is written to. Once it runs out of capacity or items,
+/// it is sealed and a new one is created right after it. The set of segments is
+/// tracked by a `Manifest` kept next to them.
+///
+/// With hints enabled, every segment that gets sealed has a `HintFile` written
+/// next to it, which requires every record to be a `KeyedRecord`.
+pub struct SegmentedLog {
+    pub dir: PathBuf,
+    pub segment_capacity: u64,
+    pub segment_max_items: u64,
+    pub segment_header_size: u64,
+    hints: bool,
+    segments: RwLock<Vec<Arc<Segment>>>,
+    report: ManifestReport,
+    /// Id and base offset new segments start from at least, past every segment
+    /// the manifest lists or the directory holds, even the ones that are gone
+    next_id: u64,
+    next_offset: u64,
+}
+
+impl SegmentedLog {
+    /// Open the log stored in `dir`, trusting only the segments listed in its manifest.
+    /// Anything that does not line up with the manifest ends up in `manifest_report`.
+    pub async fn open<P: AsRef<Path> + Clone>(opts: SegmentedLogConf<P>) -> Result<Self, DiskError> {
+        let SegmentedLogConf { dir, segment_capacity, segment_max_items, segment_header_size } = opts;
+        let dir = dir.as_ref().to_path_buf();
+
+        // Otherwise no segment could ever take a record and appending would roll forever
+        if segment_max_items == 0 || segment_capacity <= segment_header_size {
+            return Err(DiskError::InvalidSegmentConf);
+        }
+
+        tokio::fs::create_dir_all(&dir)
+            .await
+            .map_err(|e| DiskError::io(&dir, e))?;
+
+        let manifest = Manifest::load(&dir).await?.unwrap_or_default();
+        let mut report = ManifestReport::default();
+        let mut segments = Vec::with_capacity(manifest.segments.len());
+        let mut next_id = 0;
+        let mut next_offset = 0;
+
+        for entry in &manifest.segments {
+            next_id = next_id.max(entry.id + 1);
+            let path = dir.join(&entry.file_name);
+
+            // Offsets of a segment that is gone stay taken
+            let gone_until = entry.base_offset.max(entry.end_offset);
+
+            if !tokio::fs::try_exists(&path).await.map_err(|e| DiskError::io(&path, e))? {
+                next_offset = next_offset.max(gone_until);
+                report.missing.push(entry.clone());
+                continue;
+            }
+
+            match Self::open_disk(path, segment_capacity, segment_max_items, segment_header_size).await {
+                Ok(disk) if disk.metadata().created_at() == entry.created_at => {
+                    segments.push(Arc::new(Segment {
+                        id: entry.id,
+                        base_offset: entry.base_offset,
+                        disk,
+                    }));
+                }
+                Ok(_) => {
+                    next_offset = next_offset.max(gone_until);
+                    report.invalid.push((entry.clone(), DiskError::InvalidMetadata));
+                }
+                Err(e) => {
+                    next_offset = next_offset.max(gone_until);
+                    report.invalid.push((entry.clone(), e));
+                }
+            }
+        }
+
+        for (path, parsed) in Self::list_segment_files(&dir).await? {
+            let listed = manifest
+                .segments
+                .iter()
+                .any(|entry| path.file_name().and_then(|n| n.to_str()) == Some(entry.file_name.as_str()));
+
+            if !listed {
+                if let Some((id, base_offset)) = parsed {
+                    next_id = next_id.max(id + 1);
+                    next_offset = next_offset.max(base_offset);
+                }
+                report.orphaned.push(path);
+            }
+        }
+
+        if segments.is_empty() {
+            let path = dir.join(Segment::file_name(next_id, next_offset));
+            let disk = Self::open_disk(path, segment_capacity, segment_max_items, segment_header_size).await?;
+            segments.push(Arc::new(Segment { id: next_id, base_offset: next_offset, disk }));
+            Self::manifest_of(&segments).store(&dir).await?;
+        }
+
+        Ok(Self {
+            dir,
+            segment_capacity,
+            segment_max_items,
+            segment_header_size,
+            hints: false,
+            segments: RwLock::new(segments),
+            report,
+            next_id,
+            next_offset,
+        })
+    }
+
+    /// Write a hint file for every segment sealed from now on
+    pub fn with_hints(mut self) -> Self {
+        self.hints = true;
+        self
+    }
+
+    pub fn hints(&self) -> bool {
+        self.hints
+    }
+
+    /// Every `*.disk` file in `dir`, along with the id in its name if it has one
+    async fn list_segment_files(dir: &Path) -> Result<Vec<(PathBuf, Option<(u64, u64)>)>, DiskError> {
+        let mut entries = tokio::fs::read_dir(dir)
+            .await
+            .map_err(|e| DiskError::io(dir, e))?;
+        let mut found = vec![];
+
+        while let Some(entry) = entries.next_entry().await.map_err(|e| DiskError::io(dir, e))? {
+            let path = entry.path();
+
+            if path.extension().and_then(|e| e.to_str()) == Some(SEGMENT_FILE_EXTENSION) {
+                let parsed = path
+                    .file_name()
+                    .and_then(|n| n.to_str())
+                    .and_then(Segment::parse_file_name);
+                found.push((path, parsed));
+            }
+        }
+
+        found.sort();
+
+        Ok(found)
+    }
+
+    /// Open a segment, existing ones keep their capacity even when it differs
+    /// from `capacity` (e.g. compacted segments)
+    async fn open_disk(path: PathBuf, capacity: u64, max_items: u64, header_size: u64) -> Result<Disk, DiskError> {
+        let capacity = match DiskHeader::load(&path, header_size).await? {
+            Some(header) => header.capacity,
+            None => capacity,
+        };
+
+        Disk::open(DiskConf {
+            capacity,
+            max_items,
+            header_size,
+            durability: Durability::None,
+            disk_file_path: path,
+        }).await
+    }
+
+    fn manifest_of(segments: &[Arc<Segment>]) -> Manifest {
+        Manifest {
+            segments: segments.iter().map(|segment| segment.manifest_entry()).collect(),
+        }
+    }
+
+    /// What did not line up with the manifest when the log was opened
+    pub fn manifest_report(&self) -> &ManifestReport {
+        &self.report
+    }
+
+    /// The segment currently being written to
+    pub async fn active(&self) -> Arc<Segment> {
+        self.segments.read().await.last().cloned().unwrap()
+    }
+
+    /// Snapshot of every segment, oldest first
+    pub async fn segments(&self) -> Vec<Arc<Segment>> {
+        self.segments.read().await.clone()
+    }
+
+    pub async fn append(&self, data: &[u8]) -> Result<LogLocation, DiskError> {
+        loop {
+            let active = self.active().await;
+
+            match active.disk.append(data) {
+                Ok(location) => return Ok(active.to_global(location)),
+                Err(DiskError::CapacityReached) if active.disk.items() == 0 => {
+                    // Doesn't even fit in an empty segment
+                    return Err(DiskError::RecordTooLarge);
+                }
+                Err(e @ DiskError::MaxItemsReached) if active.disk.items() == 0 => return Err(e),
+                Err(DiskError::CapacityReached | DiskError::MaxItemsReached | DiskError::Locked) => {
+                    self.roll(active.id).await?;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Seal the segment `full_id` and start a new one after it.
+    /// Does nothing if another writer already rolled past it.
+    async fn roll(&self, full_id: u64) -> Result<(), DiskError> {
+        let mut segments = self.segments.write().await;
+        let active = segments.last().cloned().unwrap();
+
+        if active.id != full_id {
+            return Ok(());
+        }
+
+        active.disk.seal().await?;
+
+        // Segments listed after the active one may be gone, their offsets are still taken
+        let id = (active.id + 1).max(self.next_id);
+        let base_offset = active.next_base_offset().max(self.next_offset);
+        let path = self.dir.join(Segment::file_name(id, base_offset));
+        let disk = Self::open_disk(path, self.segment_capacity, self.segment_max_items, self.segment_header_size).await?;
+        segments.push(Arc::new(Segment { id, base_offset, disk }));
+
+        Self::manifest_of(&segments).store(&self.dir).await?;
+        drop(segments);
+
+        // The sealed segment no longer changes, scanning it doesn't need to block writers
+        if self.hints {
+            active.write_hint().await?;
+        }
+
+        Ok(())
+    }
+
+    /// Segment holding the given global offset
+    pub async fn segment_for(&self, offset: u64) -> Option<Arc<Segment>> {
+        let segments = self.segments.read().await;
+        let index = segments.partition_point(|s| s.base_offset <= offset);
+
+        index.checked_sub(1).map(|i| segments[i].clone())
+    }
+
+    pub async fn read(&self, location: LogLocation) -> Result<Vec<u8>, DiskError> {
+        let segment = self
+            .segment_for(location.offset)
+            .await
+            .ok_or(DiskError::InvalidLocation)?;
+
+        segment.disk.read_owned(segment.to_local(location))
+    }
+
+    pub async fn flush(&self) -> Result<(), DiskError> {
+        self.active().await.disk.flush().await
+    }
+
+    /// Compact every sealed segment into a single one holding the latest value of
+    /// each key, then swap it in place of the originals.
+    ///
+    /// Records in the compacted segment get new offsets, so `LogLocation`s handed
+    /// out for the replaced segments are no longer valid afterwards.
+    pub async fn compact(&self) -> Result<Option<CompactionReport>, DiskError> {
+        self.compact_with(|_, _| Ok(())).await
+    }
+
+    /// Same as `compact`, `on_swap` is called with the compacted segment and the
+    /// offset the replaced range ended at, before any read can reach the new
+    /// segment. That is the place to move locations over to it.
+    pub async fn compact_with<F>(&self, on_swap: F) -> Result<Option<CompactionReport>, DiskError>
+    where
+        F: FnOnce(&Segment, u64) -> Result<(), DiskError>,
+    {
+        let inputs: Vec<_> = {
+            let segments = self.segments.read().await;
+            segments[..segments.len() - 1]
+                .iter()
+                .take_while(|segment| segment.disk.is_sealed())
+                .cloned()
+                .collect()
+        };
+
+        let (Some(first), Some(last)) = (inputs.first(), inputs.last()) else {
+            return Ok(None);
+        };
+
+        let id = last.id;
+        let base_offset = first.base_offset;
+        let path = self.dir.join(Segment::compacted_file_name(id, base_offset));
+        let disks: Vec<&Disk> = inputs.iter().map(|segment| &segment.disk).collect();
+
+        let (disk, dropped) = compact_disks(&disks, DiskConf {
+            capacity: self.segment_capacity,
+            max_items: self.segment_max_items,
+            header_size: self.segment_header_size,
+            durability: Durability::None,
+            disk_file_path: path.clone(),
+        }).await?;
+        let compacted = Arc::new(Segment { id, base_offset, disk });
+
+        if self.hints {
+            compacted.write_hint().await?;
+        }
+
+        let mut segments = self.segments.write().await;
+
+        // Retention or another compaction got there first
+        let unchanged = segments.len() > inputs.len()
+            && segments.iter().zip(&inputs).all(|(a, b)| Arc::ptr_eq(a, b));
+        if !unchanged {
+            drop(segments);
+            Self::remove_hint(&compacted).await?;
+            drop(compacted);
+            tokio::fs::remove_file(&path)
+                .await
+                .map_err(|e| DiskError::io(&path, e))?;
+            return Ok(None);
+        }
+
+        let replaced_end = segments[inputs.len()].base_offset;
+        segments.splice(..inputs.len(), [compacted.clone()]);
+
+        // The manifest swap is what makes the compacted segment visible on disk
+        Self::manifest_of(&segments).store(&self.dir).await?;
+        on_swap(&compacted, replaced_end)?;
+        drop(segments);
+
+        for segment in &inputs {
+            tokio::fs::remove_file(&segment.disk.path)
+                .await
+                .map_err(|e| DiskError::io(&segment.disk.path, e))?;
+            Self::remove_hint(segment).await?;
+        }
+
+        Ok(Some(CompactionReport {
+            inputs: inputs.iter().map(|segment| segment.manifest_entry()).collect(),
+            output: compacted.manifest_entry(),
+            kept: compacted.disk.items(),
+            dropped,
+        }))
+    }
+
+    /// Remove the hint file of `segment`, if it has one
+    async fn remove_hint(segment: &Segment) -> Result<(), DiskError> {
+        let path = segment.hint_path();
+
+        match tokio::fs::remove_file(&path).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(DiskError::io(&path, e)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Remove the oldest sealed segments that fall outside of `policy`.
+    /// See `RetentionPolicy::select` for how `veto` is used.
+    pub async fn apply_retention<F>(&self, policy: &RetentionPolicy, veto: F) -> Result<Vec<ManifestEntry>, DiskError>
+    where
+        F: Fn(&Segment) -> bool,
+    {
+        let mut segments = self.segments.write().await;
+        let count = policy.select(&segments, SystemTime::now(), veto);
+
+        if count == 0 {
+            return Ok(vec![]);
+        }
+
+        let removed: Vec<_> = segments.drain(..count).collect();
+
+        // Forget about them before touching the files, so a crash never leaves
+        // the manifest pointing at something that is gone
+        Self::manifest_of(&segments).store(&self.dir).await?;
+
+        if let RetentionAction::Archive(archive_dir) = &policy.action {
+            tokio::fs::create_dir_all(archive_dir)
+                .await
+                .map_err(|e| DiskError::io(archive_dir, e))?;
+        }
+
+        let mut entries = Vec::with_capacity(removed.len());
+        for segment in removed {
+            let entry = segment.manifest_entry();
+            let path = &segment.disk.path;
+
+            match &policy.action {
+                RetentionAction::Delete => tokio::fs::remove_file(path).await,
+                RetentionAction::Archive(archive_dir) => {
+                    tokio::fs::rename(path, archive_dir.join(&entry.file_name)).await
+                }
+            }
+            .map_err(|e| DiskError::io(path, e))?;
+
+            let hint_path = segment.hint_path();
+            match &policy.action {
+                RetentionAction::Delete => Self::remove_hint(&segment).await?,
+                RetentionAction::Archive(archive_dir) => {
+                    if tokio::fs::try_exists(&hint_path).await.map_err(|e| DiskError::io(&hint_path, e))? {
+                        let archived = HintFile::path(&archive_dir.join(&entry.file_name));
+                        tokio::fs::rename(&hint_path, archived)
+                            .await
+                            .map_err(|e| DiskError::io(&hint_path, e))?;
+                    }
+                }
+            }
+
+            entries.push(entry);
+        }
+
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+mod segmented_log_tests {
+    use crate::keyed_record::KeyedRecord;
+    use crate::manifest::Manifest;
+    use crate::record::RECORD_HEADER_SIZE;
+    use crate::segmented_log::{Segment, SegmentedLog, SegmentedLogConf};
+    use crate::utils::test_utils::{get_folder, TEST_HEADER_SIZE};
+    use crate::DiskError;
+
+    fn conf(capacity: u64, max_items: u64) -> SegmentedLogConf<std::path::PathBuf> {
+        SegmentedLogConf {
+            dir: get_folder(None),
+            segment_capacity: capacity,
+            segment_max_items: max_items,
+            segment_header_size: TEST_HEADER_SIZE,
+        }
+    }
+
+    #[test]
+    pub fn test_segment_file_names() {
+        let name = Segment::file_name(3, 1024);
+        assert_eq!(Segment::parse_file_name(&name), Some((3, 1024)));
+        assert_eq!(Segment::parse_file_name("notes.txt"), None);
+        assert_eq!(Segment::parse_file_name("x_1.disk"), None);
+
+        let name = Segment::compacted_file_name(3, 1024);
+        assert_ne!(name, Segment::file_name(3, 1024));
+        assert_eq!(Segment::parse_file_name(&name), Some((3, 1024)));
+    }
+
+    #[tokio::test]
+    pub async fn test_rolls_over_on_max_items() {
+        let log = SegmentedLog::open(conf(1024, 2)).await.unwrap();
+
+        let mut locations = vec![];
+        for i in 0..5 {
+            locations.push(log.append(format!("record-{}", i).as_bytes()).await.unwrap());
+        }
+
+        let segments = log.segments().await;
+        assert_eq!(segments.len(), 3);
+        assert!(segments[0].disk.is_sealed());
+        assert!(segments[1].disk.is_sealed());
+        assert!(!segments[2].disk.is_sealed());
+
+        // Offsets keep growing across segments
+        for pair in locations.windows(2) {
+            assert_eq!(pair[1].offset, pair[0].offset + (RECORD_HEADER_SIZE + pair[0].length) as u64);
+        }
+
+        for (i, location) in locations.iter().enumerate() {
+            assert_eq!(log.read(*location).await.unwrap(), format!("record-{}", i).into_bytes());
+        }
+    }
+
+    #[tokio::test]
+    pub async fn test_rolls_over_on_capacity_and_reopens() {
+        let conf = conf(TEST_HEADER_SIZE + 40, 1024);
+        let log = SegmentedLog::open(conf.clone()).await.unwrap();
+
+        let mut locations = vec![];
+        for i in 0..10u8 {
+            locations.push(log.append(&[i; 12]).await.unwrap());
+        }
+        assert!(log.segments().await.len() > 1);
+        assert_eq!(log.append(&[0u8; 64]).await, Err(DiskError::RecordTooLarge));
+        log.flush().await.unwrap();
+        drop(log);
+
+        let log = SegmentedLog::open(conf).await.unwrap();
+        for (i, location) in locations.iter().enumerate() {
+            assert_eq!(log.read(*location).await.unwrap(), vec![i as u8; 12]);
+        }
+
+        let next = log.append(b"after reopen").await.unwrap();
+        assert!(next.offset > locations.last().unwrap().offset);
+        assert_eq!(log.read(next).await.unwrap(), b"after reopen");
+    }
+
+    #[tokio::test]
+    pub async fn test_reopen_keeps_larger_compacted_segment() {
+        // A single keyed record per segment
+        let conf = conf(TEST_HEADER_SIZE + 40, 1024);
+        let log = SegmentedLog::open(conf.clone()).await.unwrap();
+
+        for key in [b"a", b"b", b"c", b"d"] {
+            log.append(&KeyedRecord::put(key, b"1").to_vec()).await.unwrap();
+        }
+
+        let report = log.compact().await.unwrap().unwrap();
+        assert_eq!(report.kept, 3);
+        let compacted = log.segments().await[0].disk.capacity;
+        assert!(compacted > conf.segment_capacity);
+        log.flush().await.unwrap();
+        drop(log);
+
+        let log = SegmentedLog::open(conf).await.unwrap();
+        assert!(log.manifest_report().is_clean());
+        let segments = log.segments().await;
+        assert_eq!(segments[0].disk.capacity, compacted);
+        assert_eq!(segments[0].disk.iter().count(), 3);
+    }
+
+    #[tokio::test]
+    pub async fn test_manifest_tracks_segments() {
+        let conf = conf(1024, 1);
+        let log = SegmentedLog::open(conf.clone()).await.unwrap();
+        log.append(b"a").await.unwrap();
+        log.append(b"b").await.unwrap();
+        assert!(log.manifest_report().is_clean());
+
+        let manifest = Manifest::load(&conf.dir).await.unwrap().unwrap();
+        let segments = log.segments().await;
+        assert_eq!(manifest.segments.len(), 2);
+        assert!(manifest.segments[0].sealed);
+        assert!(!manifest.segments[1].sealed);
+        assert_eq!(manifest.segments[1].base_offset, segments[1].base_offset);
+        assert_eq!(manifest.segments[1].created_at, segments[1].disk.metadata().created_at());
+        drop(segments);
+        drop(log);
+
+        // Remove a listed segment and drop in an unknown one
+        std::fs::remove_file(conf.dir.join(&manifest.segments[0].file_name)).unwrap();
+        let orphan = conf.dir.join(Segment::file_name(7, 4096));
+        std::fs::write(&orphan, b"").unwrap();
+
+        let log = SegmentedLog::open(conf.clone()).await.unwrap();
+        let report = log.manifest_report();
+        assert_eq!(report.missing, vec![manifest.segments[0].clone()]);
+        assert_eq!(report.orphaned, vec![orphan]);
+        assert!(report.invalid.is_empty());
+        assert_eq!(log.segments().await.len(), 1);
+    }
+
+    #[tokio::test]
+    pub async fn test_refuses_segments_that_cannot_hold_a_record() {
+        let no_items = SegmentedLogConf { segment_max_items: 0, ..conf(1024, 1) };
+        assert_eq!(SegmentedLog::open(no_items).await.err(), Some(DiskError::InvalidSegmentConf));
+
+        let no_room = SegmentedLogConf { segment_capacity: TEST_HEADER_SIZE, ..conf(1024, 1) };
+        assert_eq!(SegmentedLog::open(no_room).await.err(), Some(DiskError::InvalidSegmentConf));
+    }
+
+    #[tokio::test]
+    pub async fn test_offsets_not_reused_when_the_last_segment_is_missing() {
+        let conf = conf(1024, 1);
+        let log = SegmentedLog::open(conf.clone()).await.unwrap();
+        log.append(b"a").await.unwrap();
+        let last = log.append(b"b").await.unwrap();
+        log.flush().await.unwrap();
+        let segments = log.segments().await;
+        let (missing, missing_id) = (segments[1].disk.path.clone(), segments[1].id);
+        drop(segments);
+        drop(log);
+
+        std::fs::remove_file(missing).unwrap();
+
+        let log = SegmentedLog::open(conf.clone()).await.unwrap();
+        let location = log.append(b"c").await.unwrap();
+        assert!(location.offset > last.offset);
+        assert!(log.active().await.id > missing_id);
+    }
+
+    #[tokio::test]
+    pub async fn test_offsets_not_reused_when_every_segment_is_missing() {
+        let conf = conf(1024, 1);
+        let log = SegmentedLog::open(conf.clone()).await.unwrap();
+        log.append(b"a").await.unwrap();
+        let last = log.append(b"b").await.unwrap();
+        log.flush().await.unwrap();
+        drop(log);
+
+        let manifest = Manifest::load(&conf.dir).await.unwrap().unwrap();
+        for entry in &manifest.segments {
+            std::fs::remove_file(conf.dir.join(&entry.file_name)).unwrap();
+        }
+
+        let log = SegmentedLog::open(conf.clone()).await.unwrap();
+        assert_eq!(log.manifest_report().missing, manifest.segments);
+        assert!(log.active().await.base_offset > last.offset);
+        assert!(log.append(b"c").await.unwrap().offset > last.offset);
+    }
+
+    #[tokio::test]
+    pub async fn test_compact_swaps_in_compacted_segment() {
+        let conf = conf(1024, 2);
+        let log = SegmentedLog::open(conf.clone()).await.unwrap();
+
+        log.append(&KeyedRecord::put(b"a", b"1").to_vec()).await.unwrap();
+        log.append(&KeyedRecord::put(b"b", b"1").to_vec()).await.unwrap();
+        log.append(&KeyedRecord::put(b"a", b"2").to_vec()).await.unwrap();
+        log.append(&KeyedRecord::tombstone(b"b").to_vec()).await.unwrap();
+        log.append(&KeyedRecord::put(b"c", b"1").to_vec()).await.unwrap();
+
+        let before = log.segments().await;
+        assert_eq!(before.len(), 3);
+        let old_paths: Vec<_> = before.iter().take(2).map(|s| s.disk.path.clone()).collect();
+        let active_id = before[2].id;
+        drop(before);
+
+        let report = log.compact().await.unwrap().unwrap();
+        assert_eq!(report.inputs.len(), 2);
+        assert_eq!(report.kept, 1);
+        assert_eq!(report.dropped, 3);
+        assert_eq!(report.output.id, 1);
+        assert_eq!(report.output.base_offset, 0);
+        assert!(old_paths.iter().all(|path| !path.exists()));
+
+        let segments = log.segments().await;
+        assert_eq!(segments.iter().map(|s| s.id).collect::<Vec<_>>(), vec![1, active_id]);
+
+        let compacted: Vec<Vec<u8>> = segments[0]
+            .disk
+            .iter()
+            .map(|r| KeyedRecord::from_bytes(r.unwrap().payload).unwrap().value.to_vec())
+            .collect();
+        assert_eq!(compacted, vec![b"2".to_vec()]);
+        drop(segments);
+        drop(log);
+
+        // The swap survives a reopen
+        let log = SegmentedLog::open(conf).await.unwrap();
+        assert!(log.manifest_report().is_clean());
+        assert_eq!(log.segments().await.len(), 2);
+        assert_eq!(log.compact().await.unwrap().unwrap().kept, 1);
+    }
+}
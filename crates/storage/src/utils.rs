@@ -1,4 +1,7 @@
+use std::path::Path;
 use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::AsyncWriteExt;
+use crate::DiskError;
 
 pub fn get_created_at(time: SystemTime) -> u64 {
    time
@@ -7,10 +10,42 @@ pub fn get_created_at(time: SystemTime) -> u64 {
         .as_secs()
 }
 
+/// Atomically replace `path` with `bytes`: write `tmp_path`, sync it and rename
+/// it over `path`.
+pub(crate) async fn store_atomically(path: &Path, tmp_path: &Path, bytes: &[u8]) -> Result<(), DiskError> {
+   let mut file = tokio::fs::File::create(tmp_path)
+       .await
+       .map_err(|e| DiskError::io(tmp_path, e))?;
+   file.write_all(bytes)
+       .await
+       .map_err(|e| DiskError::io(tmp_path, e))?;
+   file.sync_all().await.map_err(|e| DiskError::io(tmp_path, e))?;
+
+   tokio::fs::rename(tmp_path, path)
+       .await
+       .map_err(|e| DiskError::io(path, e))
+}
+
 #[cfg(test)]
 pub(crate) mod test_utils {
    use std::path::PathBuf;
    use uuid::Uuid;
+   use crate::disk::DiskConf;
+   use crate::durability::Durability;
+
+   /// Header region small enough for the tiny disks used in tests
+   pub const TEST_HEADER_SIZE: u64 = 134;
+
+   /// Conf of a small test disk at `path`, other values go through struct update syntax
+   pub fn disk_conf(path: PathBuf) -> DiskConf<PathBuf> {
+      DiskConf {
+         capacity: 1024,
+         max_items: 1024,
+         header_size: TEST_HEADER_SIZE,
+         durability: Durability::None,
+         disk_file_path: path,
+      }
+   }
 
    pub fn get_file(name: Option<String>, uuid: bool) -> PathBuf {
       let name = name.unwrap_or(String::from("file"));
@@ -20,9 +55,21 @@ pub(crate) mod test_utils {
          String::from("x")
       };
 
-      std::env::current_dir()
+      let folder = std::env::current_dir().unwrap().join("./test_cases");
+      std::fs::create_dir_all(&folder).unwrap();
+
+      folder.join(format!("{}_{}.bin", name, uuid))
+   }
+
+   pub fn get_folder(name: Option<String>) -> PathBuf {
+      let name = name.unwrap_or(String::from("folder"));
+      let folder = std::env::current_dir()
           .unwrap()
-          .join(format!("./test_cases/{}_{}.bin", name, uuid))
+          .join("./test_cases")
+          .join(format!("{}_{}", name, Uuid::new_v4()));
+      std::fs::create_dir_all(&folder).unwrap();
+
+      folder
    }
 
 }
\ No newline at end of file
@@ -0,0 +1,112 @@
+use std::fs::TryLockError;
+use std::path::{Path, PathBuf};
+use tokio::fs::File;
+use crate::DiskError;
+
+pub const LOCK_FILE_EXTENSION: &str = "lock";
+
+/// Exclusive, advisory OS lock (`flock`) on a disk file, held for as long as a
+/// `Disk` has it open for writing.
+///
+/// The OS releases the lock when its holder dies. The pid of the holder is also
+/// written next to the disk so that whoever fails to take the lock knows who
+/// has it, and so that a pid file left behind by a writer that crashed shows up
+/// as a stale lock the next time the disk is opened.
+pub(crate) struct WriterLock {
+    pid_path: PathBuf,
+}
+
+impl WriterLock {
+    /// Pid file of the writer of the disk at `disk_path`
+    pub fn pid_path(disk_path: &Path) -> PathBuf {
+        let mut path = disk_path.as_os_str().to_owned();
+        path.push(format!(".{}", LOCK_FILE_EXTENSION));
+        PathBuf::from(path)
+    }
+
+    /// Take the lock on `file`, which was opened from `path`.
+    ///
+    /// Also returns the pid of the previous writer if it died while holding the lock.
+    pub async fn acquire(file: File, path: &Path) -> Result<(File, Self, Option<u32>), DiskError> {
+        let file = file.into_std().await;
+        let pid_path = Self::pid_path(path);
+
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                return Err(DiskError::LockedByOtherWriter {
+                    pid: Self::read_pid(&pid_path).await,
+                });
+            }
+            Err(TryLockError::Error(e)) => return Err(DiskError::io(path, e)),
+        }
+
+        // Nobody holds the lock, so a pid file can only come from a writer that died
+        let stale = Self::read_pid(&pid_path).await;
+
+        tokio::fs::write(&pid_path, std::process::id().to_string())
+            .await
+            .map_err(|e| DiskError::io(&pid_path, e))?;
+
+        Ok((File::from_std(file), Self { pid_path }, stale))
+    }
+
+    async fn read_pid(pid_path: &Path) -> Option<u32> {
+        tokio::fs::read_to_string(pid_path).await.ok()?.trim().parse().ok()
+    }
+}
+
+impl Drop for WriterLock {
+    fn drop(&mut self) {
+        // The lock itself goes away with the file handle
+        let _ = std::fs::remove_file(&self.pid_path);
+    }
+}
+
+#[cfg(test)]
+mod writer_lock_tests {
+    use crate::disk::Disk;
+    use crate::utils::test_utils::{disk_conf, get_file};
+    use crate::writer_lock::WriterLock;
+    use crate::DiskError;
+
+    #[tokio::test]
+    pub async fn test_single_writer() {
+        let path = get_file(None, true);
+        let pid_path = WriterLock::pid_path(&path);
+
+        let disk = Disk::open(disk_conf(path.clone())).await.unwrap();
+        assert_eq!(std::fs::read_to_string(&pid_path).unwrap(), std::process::id().to_string());
+
+        assert_eq!(
+            Disk::open(disk_conf(path.clone())).await.err(),
+            Some(DiskError::LockedByOtherWriter { pid: Some(std::process::id()) })
+        );
+
+        // Readers don't need the lock
+        assert!(Disk::open_read_only(&path).await.is_ok());
+
+        drop(disk);
+        assert!(!pid_path.exists());
+
+        let disk = Disk::open(disk_conf(path)).await.unwrap();
+        assert_eq!(disk.recovery_report().stale_writer, None);
+    }
+
+    #[tokio::test]
+    pub async fn test_detects_stale_lock() {
+        let path = get_file(None, true);
+        let disk = Disk::open(disk_conf(path.clone())).await.unwrap();
+        drop(disk);
+
+        // A writer that died without cleaning up
+        std::fs::write(WriterLock::pid_path(&path), "424242").unwrap();
+
+        let disk = Disk::open(disk_conf(path.clone())).await.unwrap();
+        assert_eq!(disk.recovery_report().stale_writer, Some(424242));
+        assert_eq!(
+            std::fs::read_to_string(WriterLock::pid_path(&path)).unwrap(),
+            std::process::id().to_string()
+        );
+    }
+}